@@ -6,6 +6,7 @@ if exists("b:current_syntax")
 endif
 
 syn keyword jezDirective def globals version track
+syn keyword jezConditional if else then
 syn match jezComment ";.*$"
 
 syntax region jezString start=/"/ end=/"/
@@ -25,6 +26,7 @@ syntax match jezDelimiter "\v\}"
 
 highlight default link jezComment Comment
 highlight default link jezDirective Keyword
+highlight default link jezConditional Conditional
 highlight default link jezString String
 highlight default link jezNumber Number
 highlight default link jezOpertator Operator
@@ -1,3 +1,5 @@
+use std::fs;
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver};
 use std::thread;
 use std::time::Duration;
@@ -6,9 +8,13 @@ use serde::Serialize;
 use serde_json;
 
 use crate::err::Error;
-use crate::lang::{assemble, parser, Directive};
+use crate::lang::{compile, decode, disassemble, encode, hash_str, parser, Directive, Resolver};
 use crate::sinks::{factory, Backend, CompositeSink, Device, Sink as SinkTrait, ThreadedSink};
-use crate::vm::{millis_to_dur, Clock, Command, Instr, Machine as VmMachine, Schedule, Status};
+use crate::smf;
+use crate::vm::{
+    millis_to_dur, Clock, Command, Instr, Machine as VmMachine, Params, Schedule, Status,
+    TimeSource, Value, WallTime,
+};
 
 pub struct Sink {
     inner: Box<dyn SinkTrait>,
@@ -50,19 +56,80 @@ type Output = Box<dyn FnMut(Command)>;
 #[derive(Clone, Debug, PartialEq)]
 pub struct Program {
     instrs: Vec<Instr>,
+    files: Vec<PathBuf>,
+    dir: Option<PathBuf>,
+}
+
+/// The state of a track in a running program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackStatus {
+    /// The latest revision evaluated, a revision ahead of the one sounding
+    pub revision: usize,
+    pub muted: bool,
+    pub soloed: bool,
+    pub stopped: bool,
 }
 
 pub struct Machine {
     clock: Option<Clock>,
+    source: Option<Box<dyn TimeSource + Send>>,
     machine: VmMachine,
     channel: Receiver<Schedule<Command>>,
 }
 
 impl Program {
     pub fn new(code: &str) -> Result<Program, Error> {
+        Program::load(code, &Resolver::default())
+    }
+
+    /// Compile a program, finding the files it imports with a resolver
+    pub fn load(code: &str, resolver: &Resolver) -> Result<Program, Error> {
+        let modules = resolver.load(code)?;
         let dirs = parser(code)?;
-        let instrs = assemble(code, &dirs)?;
-        Ok(Program { instrs: instrs })
+        let instrs = compile(code, &dirs, &modules)?;
+        Ok(Program {
+            instrs,
+            files: modules.into_iter().map(|module| module.path).collect(),
+            dir: resolver.dir(),
+        })
+    }
+
+    /// Paths of the files the program imports
+    pub fn imports(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Encode the compiled program, to be run later without its source
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&self.instrs)
+    }
+
+    /// Load a program compiled with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, Error> {
+        Program::from_bytes_with(bytes, &Resolver::default())
+    }
+
+    /// Load a compiled program, finding the files it uses from the
+    /// resolver's file
+    pub fn from_bytes_with(bytes: &[u8], resolver: &Resolver) -> Result<Program, Error> {
+        Ok(Program {
+            instrs: decode(bytes)?,
+            files: vec![],
+            dir: resolver.dir(),
+        })
+    }
+
+    /// List the program's instructions, annotated with the source it was
+    /// compiled from when given
+    pub fn disassemble(&self, source: Option<&str>) -> String {
+        // Imported files are read again for the names they define
+        let imports: Vec<String> = self
+            .files
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .collect();
+        let imports: Vec<&str> = imports.iter().map(String::as_str).collect();
+        disassemble(&self.instrs, source, &imports)
     }
 }
 
@@ -79,15 +146,124 @@ impl Machine {
             output,
             Box::new(move |evt| mach_to_clock_send.send(evt).unwrap_or(())),
             &prog.instrs,
+            prog.dir.as_deref(),
         )?;
 
         Ok(Machine {
             clock: Some(clock),
+            source: None,
             machine: machine,
             channel: clock_to_mach_recv,
         })
     }
 
+    /// Send MIDI clock messages alongside note data. Stop is sent once every
+    /// track has been stopped, and continue when one is started again
+    pub fn midi_clock(&mut self) {
+        self.machine.midi_clock();
+    }
+
+    pub fn tempo(&self) -> f64 {
+        self.machine.tempo()
+    }
+
+    /// Set a parameter, read with the `param` keyword when tracks are next
+    /// evaluated
+    pub fn set_param(&self, name: &str, val: f64) {
+        self.machine.params().set(name, val);
+    }
+
+    /// A handle to the parameters that can be written from other threads
+    pub fn params(&self) -> Params {
+        self.machine.params()
+    }
+
+    /// Keep running when a reload is requested, `run_forever` and `update`
+    /// return `Status::Reload` so the host can swap in a new program with
+    /// `reload` and carry on
+    pub fn hot_reload(&mut self) {
+        self.machine.hot_reload();
+    }
+
+    /// Change the tempo from the next revision of each track
+    pub fn set_tempo(&mut self, bpm: f64) {
+        self.machine.set_tempo(bpm);
+    }
+
+    pub fn track(&mut self, name: &str) -> Option<TrackStatus> {
+        self.machine.track(hash_str(name)).map(|track| TrackStatus {
+            revision: track.revision.saturating_sub(1),
+            muted: track.muted,
+            soloed: track.soloed,
+            stopped: track.stopped,
+        })
+    }
+
+    /// Describe the current value of a global variable
+    pub fn global(&mut self, name: &str) -> Option<String> {
+        self.machine.global(hash_str(name))
+    }
+
+    /// Global variables with the values they have in the running program
+    pub(crate) fn globals(&mut self) -> Vec<(u64, Value)> {
+        self.machine.globals()
+    }
+
+    /// Silence a track while it keeps running
+    pub fn mute(&mut self, track: &str) -> Result<(), Error> {
+        self.control(Command::Mute(hash_str(track)))
+    }
+
+    pub fn unmute(&mut self, track: &str) -> Result<(), Error> {
+        self.control(Command::Unmute(hash_str(track)))
+    }
+
+    /// Only hear soloed tracks, while any are soloed
+    pub fn solo(&mut self, track: &str) -> Result<(), Error> {
+        self.control(Command::Solo(hash_str(track)))
+    }
+
+    pub fn unsolo(&mut self, track: &str) -> Result<(), Error> {
+        self.control(Command::Unsolo(hash_str(track)))
+    }
+
+    /// Stop evaluating a track once its current revision has played
+    pub fn stop_track(&mut self, track: &str) -> Result<(), Error> {
+        self.control(Command::StopTrack(hash_str(track)))
+    }
+
+    /// Restart a stopped track from its first revision
+    pub fn start_track(&mut self, track: &str) -> Result<(), Error> {
+        self.control(Command::StartTrack(hash_str(track)))
+    }
+
+    fn control(&mut self, cmd: Command) -> Result<(), Error> {
+        self.machine.process(cmd)?;
+        Ok(())
+    }
+
+    /// Hold back reloads until the next multiple of `bars`
+    pub fn quantize_reload(&mut self, bars: f64) {
+        self.machine.quantize_reload(bars);
+    }
+
+    /// Hold back reloads until the next multiple of the duration of the
+    /// longest track
+    pub fn quantize_reload_longest(&mut self) {
+        self.machine.quantize_reload_longest();
+    }
+
+    /// Swap in a new program, keeping the timing of tracks it shares with
+    /// the running program
+    pub fn reload(&mut self, prog: &Program) -> Result<(), Error> {
+        self.machine.reload(&prog.instrs)
+    }
+
+    /// Drive the clock from an external time source, when running in realtime
+    pub fn follow(&mut self, source: Box<dyn TimeSource + Send>) {
+        self.source = Some(source);
+    }
+
     pub fn schedule(&mut self, dur: f64, cmd: Command) {
         let clock = match self.clock {
             Some(ref mut clock) => clock,
@@ -120,12 +296,14 @@ impl Machine {
     }
 
     pub fn run_forever(&mut self) -> Result<Status, Error> {
-        let mut clock = match self.clock.take() {
-            Some(clock) => clock,
-            None => return Ok(Status::Stop),
-        };
-
-        thread::spawn(move || clock.run_forever());
+        // The clock keeps running when returning for a hot reload
+        if let Some(mut clock) = self.clock.take() {
+            let mut source = match self.source.take() {
+                Some(source) => source,
+                None => Box::new(WallTime::new()),
+            };
+            thread::spawn(move || clock.run_with(&mut *source));
+        }
 
         while let Ok(event) = self.channel.recv() {
             if let Schedule::At(_, cmd) = event {
@@ -143,50 +321,126 @@ impl Machine {
     }
 }
 
-pub fn simulate(duration: f64, delta: f64, program: &str) -> Result<String, Error> {
-    #[derive(Serialize)]
-    struct Results<'a> {
-        program: &'a str,
-        duration: Duration,
-        delta: Duration,
-        directives: Vec<Directive<'a>>,
-        instructions: Vec<Instr>,
-        commands: Vec<Command>,
-    }
-
+/// Run a program as a non-realtime simulation, returning its initial tempo
+/// and output commands along with the times they were sent
+fn run(duration: f64, delta: f64, program: &Program) -> Result<(f64, Vec<(f64, Command)>), Error> {
     let (sender, receiver) = channel();
-    let directives = parser(program)?;
-    let instructions = assemble(program, &directives)?;
     let mut machine = Machine::new(
-        &Program {
-            instrs: instructions.clone(),
-        },
+        program,
         Box::new(|| None),
         Box::new(move |cmd| sender.send(cmd).unwrap_or(())),
     )?;
 
     machine.schedule(duration, Command::Stop);
 
+    let tempo = machine.tempo();
     let mut commands = Vec::new();
+    let mut clock = match machine.clock.take() {
+        Some(clock) => clock,
+        None => return Ok((tempo, commands)),
+    };
+
     loop {
-        let status = machine.update(delta)?;
-        while let Ok(cmd) = receiver.try_recv() {
-            commands.push(cmd);
+        while let Ok(event) = machine.channel.try_recv() {
+            if let Schedule::At(time, cmd) = event {
+                let status = machine.machine.process(cmd)?;
+                while let Ok(cmd) = receiver.try_recv() {
+                    commands.push((time, cmd));
+                }
+                match status {
+                    Status::Continue => (),
+                    Status::Stop | Status::Reload => return Ok((tempo, commands)),
+                };
+            } else {
+                return Err(exception!());
+            }
         }
-        match status {
-            Status::Continue => continue,
-            Status::Stop | Status::Reload => break,
-        };
+
+        clock.tick(millis_to_dur(delta));
     }
+}
+
+pub fn simulate(duration: f64, delta: f64, program: &str) -> Result<String, Error> {
+    simulate_with(duration, delta, program, &Resolver::default())
+}
+
+/// Simulate a program, finding the files it imports with a resolver
+pub fn simulate_with(
+    duration: f64,
+    delta: f64,
+    program: &str,
+    resolver: &Resolver,
+) -> Result<String, Error> {
+    let modules = resolver.load(program)?;
+    let directives = parser(program)?;
+    let instructions = compile(program, &directives, &modules)?;
+    let prog = Program {
+        instrs: instructions,
+        files: modules.into_iter().map(|module| module.path).collect(),
+        dir: resolver.dir(),
+    };
+    results(duration, delta, program, directives, &prog)
+}
+
+/// Simulate a loaded program, such as one compiled by `jez build`, which
+/// is described without its source
+pub fn simulate_program(duration: f64, delta: f64, prog: &Program) -> Result<String, Error> {
+    results(duration, delta, "", vec![], prog)
+}
 
+fn results(
+    duration: f64,
+    delta: f64,
+    program: &str,
+    directives: Vec<Directive>,
+    prog: &Program,
+) -> Result<String, Error> {
+    #[derive(Serialize)]
+    struct Results<'a> {
+        program: &'a str,
+        duration: Duration,
+        delta: Duration,
+        directives: Vec<Directive<'a>>,
+        instructions: Vec<Instr>,
+        commands: Vec<Command>,
+    }
+
+    let (_, output) = run(duration, delta, prog)?;
     let results = Results {
         program: program,
         duration: millis_to_dur(duration),
         delta: millis_to_dur(delta),
         directives: directives,
-        instructions: instructions,
-        commands: commands,
+        instructions: prog.instrs.clone(),
+        commands: output.into_iter().map(|(_, cmd)| cmd).collect(),
     };
 
     Ok(serde_json::to_string(&results).unwrap())
 }
+
+/// Simulate a program, returning its output as a standard midi file
+pub fn simulate_to_smf(duration: f64, delta: f64, program: &str) -> Result<Vec<u8>, Error> {
+    simulate_to_smf_with(duration, delta, program, &Resolver::default())
+}
+
+/// Simulate a program to a standard midi file, finding the files it imports
+/// with a resolver
+pub fn simulate_to_smf_with(
+    duration: f64,
+    delta: f64,
+    program: &str,
+    resolver: &Resolver,
+) -> Result<Vec<u8>, Error> {
+    let prog = Program::load(program, resolver)?;
+    simulate_program_to_smf(duration, delta, &prog)
+}
+
+/// Simulate a loaded program to a standard midi file
+pub fn simulate_program_to_smf(
+    duration: f64,
+    delta: f64,
+    prog: &Program,
+) -> Result<Vec<u8>, Error> {
+    let (tempo, output) = run(duration, delta, prog)?;
+    Ok(smf::write(tempo, &output))
+}
@@ -1,11 +1,16 @@
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_double};
+use std::os::raw::{c_char, c_double, c_int};
+use std::ptr;
 use std::str;
 
 use std::mem;
 
-pub use crate::api::{simulate, Program};
+pub use crate::api::{simulate, Machine, Program};
 pub use crate::vm::millis_to_dur;
+use crate::vm::Status;
+
+/// Receives each command output by a machine, encoded as json
+pub type JezOutput = extern "C" fn(*const c_char);
 
 fn to_str<'a>(s: *const c_char) -> &'a str {
     if s.is_null() {
@@ -15,6 +20,16 @@ fn to_str<'a>(s: *const c_char) -> &'a str {
     c_str.to_str().unwrap()
 }
 
+fn to_machine<'a>(m: *mut Machine) -> Option<&'a mut Machine> {
+    unsafe { m.as_mut() }
+}
+
+fn free_machine(m: *mut Machine) {
+    if !m.is_null() {
+        drop(unsafe { Box::from_raw(m) });
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn jez_simulate(
     duration: c_double,
@@ -28,3 +43,52 @@ pub extern "C" fn jez_simulate(
     mem::forget(out);
     ptr
 }
+
+/// Create a machine running `program`, returns null if the program is
+/// invalid
+#[no_mangle]
+pub extern "C" fn jez_machine_new(program: *const c_char, output: JezOutput) -> *mut Machine {
+    let machine = Program::new(to_str(program)).and_then(|prog| {
+        Machine::new(
+            &prog,
+            Box::new(|| None),
+            Box::new(move |cmd| {
+                let out = serde_json::to_string(&cmd).unwrap();
+                let out = CString::new(out).unwrap();
+                output(out.as_ptr());
+            }),
+        )
+    });
+    match machine {
+        Ok(machine) => Box::into_raw(Box::new(machine)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Advance a machine by `delta` milliseconds. Returns 0 to continue, 1 when
+/// the machine has stopped, 2 when a reload was requested and -1 on error
+#[no_mangle]
+pub extern "C" fn jez_machine_update(machine: *mut Machine, delta: c_double) -> c_int {
+    let machine = match to_machine(machine) {
+        Some(machine) => machine,
+        None => return -1,
+    };
+    match machine.update(delta) {
+        Ok(Status::Continue) => 0,
+        Ok(Status::Stop) => 1,
+        Ok(Status::Reload) => 2,
+        Err(_) => -1,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn jez_machine_set_param(machine: *mut Machine, name: *const c_char, val: c_double) {
+    if let Some(machine) = to_machine(machine) {
+        machine.set_param(to_str(name), val);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn jez_machine_free(machine: *mut Machine) {
+    free_machine(machine);
+}
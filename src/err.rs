@@ -24,6 +24,15 @@ impl fmt::Display for Location {
     }
 }
 
+/// Region of program source that an error was found at, `len` characters
+/// from a line and column
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum Kind {
     Internal(Location),
@@ -37,6 +46,8 @@ pub enum Kind {
     UnknownKeyword,
     StackExhausted,
     InvalidArgs,
+    UnusedValues,
+    CyclicImport,
     Io,
 }
 
@@ -44,6 +55,9 @@ pub enum Kind {
 pub struct Error {
     pub kind: Kind,
     pub reason: Option<String>,
+    #[serde(skip)]
+    pub span: Option<Span>,
+    pub hint: Option<String>,
 }
 
 impl Error {
@@ -51,6 +65,8 @@ impl Error {
         Error {
             kind: kind,
             reason: None,
+            span: None,
+            hint: None,
         }
     }
 
@@ -58,8 +74,61 @@ impl Error {
         Error {
             kind: kind,
             reason: Some(String::from(reason)),
+            span: None,
+            hint: None,
+        }
+    }
+
+    /// Attach the source location of an error, keeping any location that
+    /// was already more specific
+    pub fn at(self, span: Span) -> Error {
+        Error {
+            span: self.span.or(Some(span)),
+            ..self
         }
     }
+
+    /// Attach a suggestion for fixing the error
+    pub fn hint(self, hint: &str) -> Error {
+        Error {
+            hint: Some(String::from(hint)),
+            ..self
+        }
+    }
+
+    /// Describe the error along with the line of source it was found in,
+    /// underlining the offending code
+    pub fn render(&self, source: &str) -> String {
+        let mut out = self.to_string();
+        let span = match self.span {
+            Some(span) => span,
+            None => return out,
+        };
+        let text = match source.lines().nth(span.line.saturating_sub(1)) {
+            Some(text) => text,
+            None => return out,
+        };
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        let indent: String = text
+            .chars()
+            .take(span.col)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(&mut out, "\n{} |\n{} | {}", gutter, span.line, text).ok();
+        write!(
+            &mut out,
+            "\n{} | {}{}",
+            gutter,
+            indent,
+            "^".repeat(span.len.max(1))
+        )
+        .ok();
+        if let Some(ref hint) = self.hint {
+            write!(&mut out, "\n{} = {}", gutter, hint).ok();
+        }
+        out
+    }
 }
 
 impl error::Error for Error {
@@ -80,6 +149,8 @@ impl error::Error for Error {
             Kind::UnknownKeyword => "unknown keyword",
             Kind::StackExhausted => "stack exhausted",
             Kind::InvalidArgs => "invalid arguments",
+            Kind::UnusedValues => "unused values",
+            Kind::CyclicImport => "cyclic import",
             Kind::Io => "I/O failure",
         }
     }
@@ -103,8 +174,15 @@ impl fmt::Display for Error {
             Kind::UnknownKeyword => write!(f, "Unknown keyword"),
             Kind::StackExhausted => write!(f, "Stack exhausted"),
             Kind::InvalidArgs => write!(f, "Invalid arguments"),
+            Kind::UnusedValues => write!(f, "Unused values"),
+            Kind::CyclicImport => write!(f, "Cyclic import"),
             Kind::Io => write!(f, "I/O failure"),
+        }?;
+
+        if let Some(span) = self.span {
+            write!(f, " on line {} at column {}", span.line, span.col)?;
         }
+        Ok(())
     }
 }
 
@@ -146,3 +224,27 @@ impl From<io::Error> for Error {
         error!(Io, &msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = ".version 0\n\n.def up 1:\n  12 ad\n";
+        let err = error!(UnknownKeyword)
+            .at(Span {
+                line: 4,
+                col: 5,
+                len: 2,
+            })
+            .hint("did you mean `add`?");
+        assert_eq!(
+            err.render(source),
+            "Unknown keyword on line 4 at column 5\n  |\n4 |   12 ad\n  |      ^^\n  = did you mean `add`?"
+        );
+
+        // Errors without a location are left as they are
+        assert_eq!(error!(InvalidArgs).render(source), "Invalid arguments");
+    }
+}
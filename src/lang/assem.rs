@@ -1,10 +1,11 @@
 use std::collections::hash_map::{DefaultHasher, Entry};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hasher;
 
 use super::dirs::{Argument, Code, Directive, Location, Name, Symbol, Value};
+use super::import::Module;
 use crate::err::Error;
-use crate::vm::Instr;
+use crate::vm::{keywords, Instr};
 
 pub fn hash_str(text: &str) -> u64 {
     let mut hasher = DefaultHasher::new();
@@ -20,6 +21,41 @@ struct Assembler<'a> {
     string_map: HashMap<&'a str, usize>,
     strings: Vec<&'a str>,
     debug: Vec<(usize, Location)>,
+    keywords: HashSet<&'static str>,
+    names: Vec<String>,
+    declared: HashSet<u64>,
+    // Files that imported functions were defined in
+    origins: HashMap<u64, String>,
+    importing: bool,
+}
+
+/// Number of single character edits to turn one word into another
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The closest word to a misspelt word, if any are close enough
+fn suggest<'b, I>(word: &str, words: I) -> Option<&'b str>
+where
+    I: Iterator<Item = &'b str>,
+{
+    let limit = (word.len() / 3).max(1);
+    words
+        .map(|other| (distance(word, other), other))
+        .filter(|&(dist, _)| dist <= limit)
+        .min()
+        .map(|(_, other)| other)
 }
 
 impl<'a> Assembler<'a> {
@@ -32,6 +68,11 @@ impl<'a> Assembler<'a> {
             string_map: HashMap::new(),
             strings: Vec::new(),
             debug: Vec::new(),
+            keywords: keywords(),
+            names: Vec::new(),
+            declared: HashSet::new(),
+            origins: HashMap::new(),
+            importing: false,
         }
     }
 
@@ -41,10 +82,10 @@ impl<'a> Assembler<'a> {
             return Err(error!(UnsupportedVersion));
         }
 
-        let arg = ((dir.arg_at(0)?).as_value()?).as_num()?;
-        let ver = arg as u64;
+        let arg = dir.arg_at(0)?;
+        let ver = (arg.as_value()?).as_num()? as u64;
         if ver != 0 {
-            return Err(error!(UnsupportedVersion));
+            return Err(error!(UnsupportedVersion).at(arg.loc()?.into()));
         }
 
         Ok(())
@@ -56,13 +97,13 @@ impl<'a> Assembler<'a> {
             match *token {
                 Argument::Kwarg(ref key, ref val) => {
                     if self.globals.contains_key(key.data) {
-                        return Err(error!(DuplicateVariable));
+                        return Err(error!(DuplicateVariable).at(key.loc.into()));
                     }
-                    let instr = self.from_value(&val.data);
+                    let instr = self.from_value(&val.data, val.loc)?;
                     self.globals.insert(key.data, instr);
                 }
-                Argument::Arg(_) => {
-                    return Err(error!(DuplicateVariable));
+                Argument::Arg(val) => {
+                    return Err(error!(DuplicateVariable).at(val.loc.into()));
                 }
             }
         }
@@ -73,33 +114,52 @@ impl<'a> Assembler<'a> {
     fn define_directive(&mut self, dir: &'a Directive) -> Result<(), Error> {
         let arg = dir.arg_at(0)?;
         let name = arg.as_value()?;
-        self.debug.push((self.instrs.len(), arg.loc()?));
+        self.locate(arg.loc()?);
 
-        let name = hash_str(name.as_keyword()?);
+        let name = name.as_keyword()?;
         let args = ((dir.arg_at(1)?).as_value()?).as_num()? as u64;
-        self.emit_func(name, args, dir)
+        self.emit_func(name, args, arg.loc()?, dir)
     }
 
     /// Define new track functions
     fn track_directive(&mut self, dir: &'a Directive) -> Result<(), Error> {
         let arg = dir.arg_at(0)?;
         let name = arg.as_value()?;
-        self.debug.push((self.instrs.len(), arg.loc()?));
+        self.locate(arg.loc()?);
 
-        let name = hash_str(name.as_keyword()?);
-        self.emit_func(name, 0, dir)?;
-        self.tracks.push(name);
+        let name = name.as_keyword()?;
+        self.emit_func(name, 0, arg.loc()?, dir)?;
+        self.tracks.push(hash_str(name));
         Ok(())
     }
 
-    fn emit_func(&mut self, name: u64, args: u64, dir: &'a Directive) -> Result<(), Error> {
+    fn emit_func(
+        &mut self,
+        word: &str,
+        args: u64,
+        loc: Location,
+        dir: &'a Directive,
+    ) -> Result<(), Error> {
+        let name = hash_str(word);
         if self.funcs.contains_key(&name) {
-            return Err(error!(DuplicateFunction));
+            let err = match self.origins.get(&name) {
+                Some(path) => {
+                    let reason = format!("`{}` is already defined in {}", word, path);
+                    error!(DuplicateFunction, &reason)
+                }
+                None => error!(DuplicateFunction),
+            };
+            return Err(err.at(loc.into()));
         }
+        self.names.push(String::from(word));
 
         self.funcs.insert(name, (args as usize, self.instrs.len()));
         self.instrs.push(Instr::Begin(name));
 
+        // Unresolved branch and quote instructions, patched once their
+        // targets are known
+        let mut branches: Vec<(Symbol, usize, Location)> = Vec::new();
+
         for token in &dir.body {
             let instr = match token.data {
                 Code::Symbol(sym) => match sym {
@@ -111,26 +171,115 @@ impl<'a> Assembler<'a> {
                     Symbol::GroupEnd => Instr::GroupEnd,
                     Symbol::Null => Instr::Null,
                     Symbol::Assign(var) => Instr::StoreVar(hash_str(var)),
+                    Symbol::If => {
+                        branches.push((sym, self.instrs.len(), token.loc));
+                        Instr::Branch(0)
+                    }
+                    Symbol::Else => {
+                        let pc = match branches.pop() {
+                            Some((Symbol::If, pc, _)) => pc,
+                            _ => return Err(error!(UnexpectedToken).at(token.loc.into())),
+                        };
+                        // Skip over the jump when the condition is false
+                        self.instrs[pc] = Instr::Branch(self.instrs.len() + 1);
+                        branches.push((sym, self.instrs.len(), token.loc));
+                        Instr::Jump(0)
+                    }
+                    Symbol::Then => {
+                        let pc = self.instrs.len();
+                        match branches.pop() {
+                            Some((Symbol::If, at, _)) => self.instrs[at] = Instr::Branch(pc),
+                            Some((Symbol::Else, at, _)) => self.instrs[at] = Instr::Jump(pc),
+                            _ => return Err(error!(UnexpectedToken).at(token.loc.into())),
+                        };
+                        continue;
+                    }
+                    Symbol::QuoteBegin => {
+                        branches.push((sym, self.instrs.len(), token.loc));
+                        Instr::Quote(0)
+                    }
+                    Symbol::QuoteEnd => {
+                        let pc = match branches.pop() {
+                            Some((Symbol::QuoteBegin, pc, _)) => pc,
+                            _ => return Err(error!(UnexpectedToken).at(token.loc.into())),
+                        };
+                        // Quote bodies include their trailing return
+                        self.instrs[pc] = Instr::Quote(self.instrs.len() - pc);
+                        Instr::Return
+                    }
                 },
-                Code::Value(ref val) => self.from_value(val),
+                Code::Value(ref val) => self.from_value(val, token.loc)?,
             };
-            self.debug.push((self.instrs.len(), token.loc));
+            self.locate(token.loc);
             self.instrs.push(instr);
         }
 
+        // Point at the innermost branch or quote left open
+        if let Some(&(_, _, loc)) = branches.last() {
+            return Err(error!(IncompleteInput).at(loc.into()));
+        }
+
         self.instrs.push(Instr::Return);
         self.instrs.push(Instr::End(name));
         Ok(())
     }
 
+    /// Map the next instruction to the token it was assembled from, only
+    /// the program itself is mapped as imported files have their own source
+    fn locate(&mut self, loc: Location) {
+        if !self.importing {
+            self.debug.push((self.instrs.len(), loc));
+        }
+    }
+
+    fn declare(&mut self, dirs: &'a [Directive]) {
+        self.declared.clear();
+        for dir in dirs {
+            if let Name::Def | Name::Track = dir.name.data {
+                if let Ok(Value::Keyword(word)) = dir.arg_at(0).and_then(|arg| arg.as_value()) {
+                    self.declared.insert(hash_str(word));
+                }
+            }
+        }
+    }
+
+    /// Define the functions and global variables of an imported file, its
+    /// tracks are left out
+    pub fn import(&mut self, path: &str, dirs: &'a [Directive]) -> Result<(), Error> {
+        self.declare(dirs);
+        self.importing = true;
+        for dir in dirs {
+            match dir.name.data {
+                Name::Version => self.version_directive(dir),
+                Name::Globals => self.globals_directive(dir),
+                Name::Def => {
+                    let res = self.define_directive(dir);
+                    if let Ok(Value::Keyword(word)) = dir.arg_at(0).and_then(|arg| arg.as_value()) {
+                        self.origins.insert(hash_str(word), String::from(path));
+                    }
+                    res
+                }
+                Name::Track | Name::Import => Ok(()),
+            }
+            .map_err(|err| err.at(dir.name.loc.into()))?;
+        }
+        self.importing = false;
+        Ok(())
+    }
+
     pub fn assemble(&mut self, prog: &'a str, dirs: &'a [Directive]) -> Result<Vec<Instr>, Error> {
+        self.declare(dirs);
+
         for dir in dirs {
             match dir.name.data {
                 Name::Version => self.version_directive(dir),
                 Name::Globals => self.globals_directive(dir),
                 Name::Def => self.define_directive(dir),
                 Name::Track => self.track_directive(dir),
-            }?;
+                // Imported files are assembled beforehand
+                Name::Import => Ok(()),
+            }
+            .map_err(|err| err.at(dir.name.loc.into()))?;
         }
 
         self.instrs.push(Instr::Begin(0));
@@ -180,8 +329,32 @@ impl<'a> Assembler<'a> {
         Ok(self.instrs.clone())
     }
 
-    fn from_value(&mut self, value: &'a Value) -> Instr {
-        match *value {
+    fn unknown_keyword(&self, word: &str, loc: Location) -> Error {
+        let reason = format!("`{}` is not a keyword or function", word);
+        let err = error!(UnknownKeyword, &reason).at(loc.into());
+
+        // Functions can only be called once they have been defined
+        if self.declared.contains(&hash_str(word)) {
+            let hint = format!(
+                "`{}` is defined after it is used, move its definition up",
+                word
+            );
+            return err.hint(&hint);
+        }
+
+        let words = self
+            .keywords
+            .iter()
+            .cloned()
+            .chain(self.names.iter().map(String::as_str));
+        match suggest(word, words) {
+            Some(other) => err.hint(&format!("did you mean `{}`?", other)),
+            None => err,
+        }
+    }
+
+    fn from_value(&mut self, value: &'a Value, loc: Location) -> Result<Instr, Error> {
+        Ok(match *value {
             Value::Variable(var) => Instr::LoadVar(hash_str(var)),
 
             Value::Number(num) => Instr::LoadNumber(num),
@@ -206,22 +379,43 @@ impl<'a> Assembler<'a> {
                 if self.funcs.contains_key(&sym) {
                     let (args, pc) = self.funcs[&sym];
                     Instr::Call(args, pc)
-                } else {
+                } else if self.keywords.contains(word) {
                     Instr::Keyword(sym)
+                } else {
+                    return Err(self.unknown_keyword(word, loc));
                 }
             }
-        }
+        })
     }
 }
 
+#[cfg(test)]
 pub fn assemble(prog: &str, dirs: &[Directive]) -> Result<Vec<Instr>, Error> {
     Assembler::new().assemble(prog, dirs)
 }
 
+/// Assemble a program after the files it imports, in the order they must
+/// be defined in
+pub fn assemble_with<'a>(
+    prog: &'a str,
+    dirs: &'a [Directive],
+    imports: &'a [(&Module, Vec<Directive<'a>>)],
+) -> Result<Vec<Instr>, Error> {
+    let mut assem = Assembler::new();
+    for (module, dirs) in imports {
+        assem
+            .import(&module.name, dirs)
+            .map_err(|err| module.error(err))?;
+    }
+    assem.assemble(prog, dirs)
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::dirs::Token;
+    use super::super::parse::parser;
     use super::*;
+    use crate::err::{Kind, Span};
 
     #[test]
     fn test_strings() {
@@ -290,6 +484,133 @@ mod tests {
         assert_eq!(def, String::from("def"));
     }
 
+    #[test]
+    fn test_branches() {
+        let code = ".def main 0: 1 if 2 else 3 then 4";
+        let dirs = parser(code).unwrap();
+        let result = assemble(code, &dirs).unwrap();
+        assert_eq!(
+            &result[..8],
+            &[
+                Instr::Begin(17450787904383802648),
+                Instr::LoadNumber(1.0),
+                Instr::Branch(5),
+                Instr::LoadNumber(2.0),
+                Instr::Jump(6),
+                Instr::LoadNumber(3.0),
+                Instr::LoadNumber(4.0),
+                Instr::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quotations() {
+        let code = ".def main 0: [: 1 [: 2 :] :] apply";
+        let dirs = parser(code).unwrap();
+        let result = assemble(code, &dirs).unwrap();
+        assert_eq!(
+            &result[..9],
+            &[
+                Instr::Begin(17450787904383802648),
+                Instr::Quote(5),
+                Instr::LoadNumber(1.0),
+                Instr::Quote(2),
+                Instr::LoadNumber(2.0),
+                Instr::Return,
+                Instr::Return,
+                Instr::Keyword(hash_str("apply")),
+                Instr::Return,
+            ]
+        );
+
+        let code = ".def main 0: [: 1 if :]";
+        let dirs = parser(code).unwrap();
+        assert_eq!(
+            assemble(code, &dirs).unwrap_err().kind,
+            Kind::UnexpectedToken
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_branches() {
+        let code = ".def main 0: 1 if 2 else 3";
+        let dirs = parser(code).unwrap();
+        assert_eq!(
+            assemble(code, &dirs).unwrap_err().kind,
+            Kind::IncompleteInput
+        );
+
+        let code = ".def main 0: 1 then";
+        let dirs = parser(code).unwrap();
+        assert_eq!(
+            assemble(code, &dirs).unwrap_err().kind,
+            Kind::UnexpectedToken
+        );
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let code = ".version 0\n.def up 1:\n  12 ad\n.def main 0:\n  [: 1 then";
+        let err = assemble(code, &parser(code).unwrap()).unwrap_err();
+        assert_eq!(err.kind, Kind::UnknownKeyword);
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 3,
+                col: 5,
+                len: 2
+            })
+        );
+        assert_eq!(err.hint, Some(String::from("did you mean `add`?")));
+
+        let code = ".version 0\n.def main 0:\n  [: 1 :] up\n.def up 0: 1";
+        let err = assemble(code, &parser(code).unwrap()).unwrap_err();
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 3,
+                col: 10,
+                len: 2
+            })
+        );
+        assert!(err.hint.unwrap().contains("defined after it is used"));
+
+        let code = ".version 0\n.def main 0:\n  [: 1 then";
+        let err = assemble(code, &parser(code).unwrap()).unwrap_err();
+        assert_eq!(err.kind, Kind::UnexpectedToken);
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 3,
+                col: 7,
+                len: 4
+            })
+        );
+
+        let code = ".version 0\n.def main 0:\n  1 if 2";
+        let err = assemble(code, &parser(code).unwrap()).unwrap_err();
+        assert_eq!(err.kind, Kind::IncompleteInput);
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 3,
+                col: 4,
+                len: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(distance("rotat", "rotate"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        let words = ["rotate", "reverse", "repeat"];
+        assert_eq!(suggest("rotat", words.iter().cloned()), Some("rotate"));
+        assert_eq!(suggest("revrse", words.iter().cloned()), Some("reverse"));
+        assert_eq!(suggest("xyz", words.iter().cloned()), None);
+    }
+
     #[test]
     fn test_simple() {
         let dirs = vec![
@@ -0,0 +1,304 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::err::Error;
+use crate::vm::Instr;
+
+// Compiled programs are laid out as follows, all numbers little endian
+//
+// magic    : "JEZC"
+// version  : u16, the version of this layout
+// count    : u32, number of instructions
+// records  : an opcode (u8) followed by its operands, in program order
+//
+// Entries of the string table, a `StoreString` with the `RawData` holding
+// its bytes, are stored as a single record as are the debug `SourceLoc`s.
+// Opcodes are fixed so that programs can be loaded by later versions.
+
+const MAGIC: &[u8; 4] = b"JEZC";
+const FORMAT_VERSION: u16 = 1;
+
+const BEGIN: u8 = 0x01;
+const END: u8 = 0x02;
+const CALL: u8 = 0x03;
+const RETURN: u8 = 0x04;
+const JUMP: u8 = 0x05;
+const BRANCH: u8 = 0x06;
+const QUOTE: u8 = 0x07;
+const LOAD_NUMBER: u8 = 0x10;
+const LOAD_SYMBOL: u8 = 0x11;
+const LOAD_VAR: u8 = 0x12;
+const LOAD_STRING: u8 = 0x13;
+const STORE_GLOB: u8 = 0x14;
+const STORE_VAR: u8 = 0x15;
+const KEYWORD: u8 = 0x16;
+const LIST_BEGIN: u8 = 0x20;
+const LIST_END: u8 = 0x21;
+const SEQ_BEGIN: u8 = 0x22;
+const SEQ_END: u8 = 0x23;
+const GROUP_BEGIN: u8 = 0x24;
+const GROUP_END: u8 = 0x25;
+const NULL: u8 = 0x26;
+const STRING: u8 = 0x30;
+const STORE_STRING: u8 = 0x31;
+const RAW_DATA: u8 = 0x32;
+const SOURCE_LOC: u8 = 0x40;
+
+/// Encode instructions in the compiled program format
+pub fn encode(instrs: &[Instr]) -> Vec<u8> {
+    let mut buff = Vec::new();
+    buff.extend_from_slice(MAGIC);
+    buff.write_u16::<LittleEndian>(FORMAT_VERSION).unwrap();
+    buff.write_u32::<LittleEndian>(instrs.len() as u32).unwrap();
+
+    let mut pc = 0;
+    while pc < instrs.len() {
+        let op = |buff: &mut Vec<u8>, code: u8, args: &[u64]| {
+            buff.push(code);
+            for arg in args {
+                buff.write_u64::<LittleEndian>(*arg).unwrap();
+            }
+        };
+
+        match instrs[pc] {
+            Instr::Begin(name) => op(&mut buff, BEGIN, &[name]),
+            Instr::End(name) => op(&mut buff, END, &[name]),
+            Instr::Call(args, addr) => op(&mut buff, CALL, &[args as u64, addr as u64]),
+            Instr::Return => op(&mut buff, RETURN, &[]),
+            Instr::Jump(addr) => op(&mut buff, JUMP, &[addr as u64]),
+            Instr::Branch(addr) => op(&mut buff, BRANCH, &[addr as u64]),
+            Instr::Quote(len) => op(&mut buff, QUOTE, &[len as u64]),
+            Instr::LoadNumber(num) => op(&mut buff, LOAD_NUMBER, &[num.to_bits()]),
+            Instr::LoadSymbol(sym) => op(&mut buff, LOAD_SYMBOL, &[sym]),
+            Instr::LoadVar(var) => op(&mut buff, LOAD_VAR, &[var]),
+            Instr::LoadString(id) => op(&mut buff, LOAD_STRING, &[id]),
+            Instr::StoreGlob(var) => op(&mut buff, STORE_GLOB, &[var]),
+            Instr::StoreVar(var) => op(&mut buff, STORE_VAR, &[var]),
+            Instr::Keyword(sym) => op(&mut buff, KEYWORD, &[sym]),
+            Instr::ListBegin => op(&mut buff, LIST_BEGIN, &[]),
+            Instr::ListEnd => op(&mut buff, LIST_END, &[]),
+            Instr::SeqBegin => op(&mut buff, SEQ_BEGIN, &[]),
+            Instr::SeqEnd => op(&mut buff, SEQ_END, &[]),
+            Instr::GroupBegin => op(&mut buff, GROUP_BEGIN, &[]),
+            Instr::GroupEnd => op(&mut buff, GROUP_END, &[]),
+            Instr::Null => op(&mut buff, NULL, &[]),
+            Instr::StoreString(id, len) => {
+                let bytes: Vec<u8> = instrs[pc + 1..]
+                    .iter()
+                    .take(len as usize)
+                    .filter_map(|instr| match *instr {
+                        Instr::RawData(byte) => Some(byte),
+                        _ => None,
+                    })
+                    .collect();
+                if bytes.len() as u64 == len {
+                    op(&mut buff, STRING, &[id, len]);
+                    buff.extend_from_slice(&bytes);
+                    pc += bytes.len();
+                } else {
+                    op(&mut buff, STORE_STRING, &[id, len]);
+                }
+            }
+            Instr::RawData(byte) => {
+                buff.push(RAW_DATA);
+                buff.push(byte);
+            }
+            Instr::SourceLoc(addr, id, line, col) => {
+                op(&mut buff, SOURCE_LOC, &[addr, id, line, col]);
+            }
+        }
+        pc += 1;
+    }
+    buff
+}
+
+/// Decode instructions from the compiled program format
+pub fn decode(bytes: &[u8]) -> Result<Vec<Instr>, Error> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(error!(UnexpectedToken, "Not a compiled program"));
+    }
+    let mut rdr = Cursor::new(&bytes[MAGIC.len()..]);
+    let truncated = |_| error!(IncompleteInput, "Compiled program is truncated");
+
+    let version = rdr.read_u16::<LittleEndian>().map_err(truncated)?;
+    if version != FORMAT_VERSION {
+        let reason = format!(
+            "Compiled program has version {}, expected {}",
+            version, FORMAT_VERSION
+        );
+        return Err(error!(UnsupportedVersion, &reason));
+    }
+    let count = rdr.read_u32::<LittleEndian>().map_err(truncated)? as usize;
+
+    // Every instruction takes at least a byte, so sizes are checked against
+    // what is left before anything is allocated
+    let size = bytes.len() - MAGIC.len();
+    if count > size - rdr.position() as usize {
+        return Err(error!(IncompleteInput, "Compiled program is truncated"));
+    }
+
+    let mut instrs = Vec::with_capacity(count);
+    while instrs.len() < count {
+        let code = rdr.read_u8().map_err(truncated)?;
+        let mut arg = || rdr.read_u64::<LittleEndian>().map_err(truncated);
+        let instr = match code {
+            BEGIN => Instr::Begin(arg()?),
+            END => Instr::End(arg()?),
+            CALL => Instr::Call(arg()? as usize, arg()? as usize),
+            RETURN => Instr::Return,
+            JUMP => Instr::Jump(arg()? as usize),
+            BRANCH => Instr::Branch(arg()? as usize),
+            QUOTE => Instr::Quote(arg()? as usize),
+            LOAD_NUMBER => Instr::LoadNumber(f64::from_bits(arg()?)),
+            LOAD_SYMBOL => Instr::LoadSymbol(arg()?),
+            LOAD_VAR => Instr::LoadVar(arg()?),
+            LOAD_STRING => Instr::LoadString(arg()?),
+            STORE_GLOB => Instr::StoreGlob(arg()?),
+            STORE_VAR => Instr::StoreVar(arg()?),
+            KEYWORD => Instr::Keyword(arg()?),
+            LIST_BEGIN => Instr::ListBegin,
+            LIST_END => Instr::ListEnd,
+            SEQ_BEGIN => Instr::SeqBegin,
+            SEQ_END => Instr::SeqEnd,
+            GROUP_BEGIN => Instr::GroupBegin,
+            GROUP_END => Instr::GroupEnd,
+            NULL => Instr::Null,
+            STRING => {
+                let (id, len) = (arg()?, arg()?);
+                if len > (size - rdr.position() as usize) as u64 {
+                    return Err(error!(IncompleteInput, "Compiled program is truncated"));
+                }
+                let mut bytes = vec![0; len as usize];
+                rdr.read_exact(&mut bytes).map_err(truncated)?;
+                instrs.push(Instr::StoreString(id, len));
+                instrs.extend(bytes.into_iter().map(Instr::RawData));
+                continue;
+            }
+            STORE_STRING => Instr::StoreString(arg()?, arg()?),
+            RAW_DATA => Instr::RawData(rdr.read_u8().map_err(truncated)?),
+            SOURCE_LOC => Instr::SourceLoc(arg()?, arg()?, arg()?, arg()?),
+            _ => {
+                let reason = format!("Unknown opcode {:#04x}", code);
+                return Err(error!(UnexpectedToken, &reason));
+            }
+        };
+        instrs.push(instr);
+    }
+
+    if instrs.len() != count || rdr.position() as usize != size {
+        return Err(error!(
+            UnexpectedToken,
+            "Compiled program has trailing data"
+        ));
+    }
+    validate(&instrs)?;
+    Ok(instrs)
+}
+
+/// Check the addresses and lengths held by instructions stay within the
+/// program, as the interpreter trusts those it was compiled with
+fn validate(instrs: &[Instr]) -> Result<(), Error> {
+    let count = instrs.len();
+    for (pc, instr) in instrs.iter().enumerate() {
+        let valid = match *instr {
+            // Jumps land after the instruction before their target
+            Instr::Jump(addr) | Instr::Branch(addr) => addr > 0 && addr <= count,
+            Instr::Call(_, addr) => addr < count,
+            Instr::Quote(len) => len < count - pc,
+            Instr::StoreString(_, len) => len < (count - pc) as u64,
+            _ => true,
+        };
+        if !valid {
+            let reason = format!("Instruction {} points outside of the program", pc);
+            return Err(error!(UnexpectedToken, &reason));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{assemble, parser};
+    use super::*;
+    use crate::err::Kind;
+
+    const PROGRAM: &str = ".version 0
+
+.globals @root = 60
+
+.def transpose 2:
+  swap = @amount
+  [: @amount add :] map
+
+.track t1:
+  (@root 62 64) [: 60 gt :] filter
+  12 transpose \"tests/files/midi_file.mid\" 1 midi_file
+  revision 2 modulo 0 eq if -0.5 else { 1 ~ } then 200 1 midi_out
+";
+
+    fn instrs() -> Vec<Instr> {
+        assemble(PROGRAM, &parser(PROGRAM).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let instrs = instrs();
+        let bytes = encode(&instrs);
+        assert_eq!(&bytes[..4], b"JEZC");
+        assert_eq!(decode(&bytes).unwrap(), instrs);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let bytes = encode(&instrs());
+        assert_eq!(decode(b"JEZ").unwrap_err().kind, Kind::UnexpectedToken);
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 1]).unwrap_err().kind,
+            Kind::IncompleteInput
+        );
+
+        let mut later = bytes.clone();
+        later[4] = 2;
+        assert_eq!(decode(&later).unwrap_err().kind, Kind::UnsupportedVersion);
+
+        let mut unknown = bytes.clone();
+        unknown[10] = 0xff;
+        assert_eq!(decode(&unknown).unwrap_err().kind, Kind::UnexpectedToken);
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert_eq!(decode(&trailing).unwrap_err().kind, Kind::UnexpectedToken);
+    }
+
+    #[test]
+    fn test_sizes_checked() {
+        // Counts and lengths larger than the input aren't allocated
+        let mut huge = b"JEZC\x01\x00".to_vec();
+        huge.extend_from_slice(&[0xff; 4]);
+        assert_eq!(decode(&huge).unwrap_err().kind, Kind::IncompleteInput);
+
+        let mut string = b"JEZC\x01\x00\x01\x00\x00\x00\x30".to_vec();
+        string.extend_from_slice(&[0; 8]);
+        string.extend_from_slice(&[0xff; 8]);
+        assert_eq!(decode(&string).unwrap_err().kind, Kind::IncompleteInput);
+    }
+
+    #[test]
+    fn test_addresses_checked() {
+        let programs = [
+            vec![Instr::Begin(0), Instr::Jump(0), Instr::End(0)],
+            vec![Instr::Begin(0), Instr::Branch(4), Instr::End(0)],
+            vec![Instr::Begin(0), Instr::Call(0, 3), Instr::End(0)],
+            vec![Instr::Begin(0), Instr::Quote(2), Instr::End(0)],
+            vec![Instr::Begin(0), Instr::StoreString(0, 2), Instr::RawData(0)],
+        ];
+        for instrs in programs.iter() {
+            let err = decode(&encode(instrs)).unwrap_err();
+            assert_eq!(err.kind, Kind::UnexpectedToken);
+        }
+
+        let instrs = vec![Instr::Begin(0), Instr::Jump(3), Instr::End(0)];
+        assert_eq!(decode(&encode(&instrs)).unwrap(), instrs);
+    }
+}
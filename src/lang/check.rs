@@ -0,0 +1,527 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::assem::hash_str;
+use super::dirs::{Directive, Name, Value};
+use crate::err::{Error, Span};
+use crate::vm::{docs, Instr};
+
+/// Types of values as declared in stack effects
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Type {
+    Any,
+    Num,
+    Sym,
+    Str,
+    List,
+    Seq,
+    Curve,
+    Quote,
+}
+
+impl Type {
+    fn parse(name: &str) -> Option<Type> {
+        Some(match name {
+            "any" => Type::Any,
+            "num" => Type::Num,
+            "sym" => Type::Sym,
+            "str" => Type::Str,
+            "list" => Type::List,
+            "seq" => Type::Seq,
+            "curve" => Type::Curve,
+            "quote" => Type::Quote,
+            _ => return None,
+        })
+    }
+
+    /// Whether a value of this type can be used where another is expected
+    fn fits(self, expected: Type) -> bool {
+        self == expected
+            || self == Type::Any
+            || expected == Type::Any
+            || (self == Type::Seq && expected == Type::List)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Type::Any => "any",
+            Type::Num => "num",
+            Type::Sym => "sym",
+            Type::Str => "str",
+            Type::List => "list",
+            Type::Seq => "seq",
+            Type::Curve => "curve",
+            Type::Quote => "quote",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A value taken from or put on the stack by a keyword
+#[derive(Clone, Debug, PartialEq)]
+struct Param {
+    name: &'static str,
+    ty: Type,
+    // Whether the number of values is only known at runtime
+    variable: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Effect {
+    inputs: Vec<Param>,
+    outputs: Vec<Param>,
+}
+
+/// Parse a stack effect such as `( values:list amount:num -- values:list )`
+fn parse_effect(effect: &'static str) -> Option<Effect> {
+    let effect = effect.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let mut sides = effect.split("--");
+    let mut params = || -> Option<Vec<Param>> {
+        sides
+            .next()?
+            .split_whitespace()
+            .map(|param| {
+                let variable = param.ends_with('?') || param.ends_with('*');
+                let param = param.trim_end_matches(&['?', '*'][..]);
+                let (name, ty) = match param.find(':') {
+                    Some(idx) => (&param[..idx], &param[idx + 1..]),
+                    None => ("", param),
+                };
+                Some(Param {
+                    name,
+                    ty: Type::parse(ty)?,
+                    variable,
+                })
+            })
+            .collect()
+    };
+    let inputs = params()?;
+    let outputs = params()?;
+    Some(Effect { inputs, outputs })
+}
+
+/// A value on the stack during analysis
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Slot {
+    Value(Type),
+    // The start of a list, values can't be taken from beyond it
+    Marker,
+    // Any number of values, only known at runtime
+    Unknown,
+}
+
+/// A function being checked
+#[derive(Copy, Clone, Debug)]
+struct Function<'a> {
+    name: &'a str,
+    args: u64,
+    returns: bool,
+}
+
+struct Checker<'a> {
+    instrs: &'a [Instr],
+    effects: HashMap<u64, (&'static str, Effect)>,
+    names: HashMap<u64, &'a str>,
+    locations: HashMap<usize, Span>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(instrs: &'a [Instr], dirs: &'a [Directive]) -> Checker<'a> {
+        let effects = docs()
+            .into_iter()
+            .filter_map(|(name, doc)| Some((hash_str(name), (name, parse_effect(doc.effect)?))))
+            .collect();
+
+        let mut names = HashMap::new();
+        for dir in dirs {
+            if let Ok(Value::Keyword(name)) = dir.arg_at(0).and_then(|arg| arg.as_value()) {
+                names.insert(hash_str(name), name);
+            }
+        }
+
+        // Decode the tokens that instructions were assembled from
+        let mut strings = HashMap::new();
+        for (pc, instr) in instrs.iter().enumerate() {
+            if let Instr::StoreString(id, len) = *instr {
+                let bytes: Vec<u8> = instrs[pc + 1..]
+                    .iter()
+                    .take(len as usize)
+                    .filter_map(|instr| match *instr {
+                        Instr::RawData(byte) => Some(byte),
+                        _ => None,
+                    })
+                    .collect();
+                strings.insert(id, String::from_utf8_lossy(&bytes).len());
+            }
+        }
+        let mut locations = HashMap::new();
+        for instr in instrs {
+            if let Instr::SourceLoc(pc, id, line, col) = *instr {
+                let span = Span {
+                    line: line as usize,
+                    col: col as usize,
+                    len: strings.get(&id).cloned().unwrap_or(0),
+                };
+                locations.insert(pc as usize, span);
+            }
+        }
+
+        Checker {
+            instrs,
+            effects,
+            names,
+            locations,
+        }
+    }
+
+    fn error(&self, err: Error, pc: usize) -> Error {
+        match self.locations.get(&pc) {
+            Some(span) => err.at(*span),
+            None => err,
+        }
+    }
+
+    /// Take a value from the stack, failing when there is none to take
+    fn pop(stack: &mut Vec<Slot>) -> Option<Type> {
+        match stack.last().cloned() {
+            Some(Slot::Value(ty)) => {
+                stack.pop();
+                Some(ty)
+            }
+            Some(Slot::Unknown) => Some(Type::Any),
+            Some(Slot::Marker) | None => None,
+        }
+    }
+
+    /// Number of values that can be taken from the stack
+    fn available(stack: &[Slot]) -> usize {
+        stack
+            .iter()
+            .rev()
+            .take_while(|slot| **slot != Slot::Marker)
+            .count()
+    }
+
+    fn underflow(&self, func: Function, stack: &[Slot], word: &str, needs: usize) -> Error {
+        let found = Checker::available(stack);
+        let reason = format!("`{}` needs {} values but finds {}", word, needs, found);
+        let err = error!(StackExhausted, &reason);
+        if stack.contains(&Slot::Marker) {
+            return err;
+        }
+        let hint = format!("`{}` is declared with {} arguments", func.name, func.args);
+        err.hint(&hint)
+    }
+
+    fn keyword(&self, func: Function, stack: &mut Vec<Slot>, sym: u64) -> Result<(), Error> {
+        let (word, effect) = match self.effects.get(&sym) {
+            Some(effect) => effect,
+            None => {
+                stack.push(Slot::Unknown);
+                return Ok(());
+            }
+        };
+
+        let needs = effect.inputs.len();
+        if needs > Checker::available(stack) && !stack.contains(&Slot::Unknown) {
+            return Err(self.underflow(func, stack, word, needs));
+        }
+
+        let mut inputs = HashMap::new();
+        for param in effect.inputs.iter().rev() {
+            let ty = match Checker::pop(stack) {
+                Some(ty) => ty,
+                None => return Err(self.underflow(func, stack, word, needs)),
+            };
+            if !ty.fits(param.ty) {
+                let reason = format!(
+                    "`{}` expects {} for `{}` but finds {}",
+                    word, param.ty, param.name, ty
+                );
+                return Err(error!(InvalidArgs, &reason));
+            }
+            inputs.insert(param.name, ty);
+        }
+
+        for param in &effect.outputs {
+            if param.variable {
+                stack.push(Slot::Unknown);
+                continue;
+            }
+            // Values passed through keep the type they were given as
+            let ty = inputs.get(param.name).cloned().unwrap_or(param.ty);
+            stack.push(Slot::Value(ty));
+        }
+        Ok(())
+    }
+
+    /// Check every path through the body of a function, or a quotation
+    fn function(&self, func: Function, start: usize, stack: Vec<Slot>) -> Result<(), Error> {
+        let mut paths = vec![(start, stack)];
+        let mut seen = HashSet::new();
+
+        while let Some((mut pc, mut stack)) = paths.pop() {
+            loop {
+                if !seen.insert((pc, format!("{:?}", stack))) {
+                    break;
+                }
+                let instr = match self.instrs.get(pc) {
+                    Some(instr) => *instr,
+                    None => break,
+                };
+
+                let loaded = match instr {
+                    Instr::Null | Instr::LoadVar(_) => Some(Type::Any),
+                    Instr::LoadNumber(_) => Some(Type::Num),
+                    Instr::LoadSymbol(_) => Some(Type::Sym),
+                    Instr::LoadString(_) => Some(Type::Str),
+                    _ => None,
+                };
+                if let Some(ty) = loaded {
+                    stack.push(Slot::Value(ty));
+                    pc += 1;
+                    continue;
+                }
+
+                let res = match instr {
+                    Instr::StoreVar(_) | Instr::StoreGlob(_) => match Checker::pop(&mut stack) {
+                        Some(_) => Ok(()),
+                        None => Err(self.underflow(func, &stack, "=", 1)),
+                    },
+                    Instr::ListBegin | Instr::SeqBegin | Instr::GroupBegin => {
+                        stack.push(Slot::Marker);
+                        Ok(())
+                    }
+                    Instr::ListEnd | Instr::SeqEnd | Instr::GroupEnd => {
+                        match stack.iter().rposition(|slot| *slot == Slot::Marker) {
+                            Some(idx) => {
+                                stack.truncate(idx);
+                                let ty = if instr == Instr::SeqEnd {
+                                    Type::Seq
+                                } else {
+                                    Type::List
+                                };
+                                stack.push(Slot::Value(ty));
+                                Ok(())
+                            }
+                            None => Err(error!(UnexpectedToken)),
+                        }
+                    }
+                    Instr::Quote(len) => {
+                        // Quotations are given their arguments when applied
+                        let quote = Function {
+                            returns: false,
+                            ..func
+                        };
+                        self.function(quote, pc + 1, vec![Slot::Unknown])?;
+                        stack.push(Slot::Value(Type::Quote));
+                        pc += len;
+                        Ok(())
+                    }
+                    Instr::Jump(target) => {
+                        pc = target;
+                        continue;
+                    }
+                    Instr::Branch(target) => match Checker::pop(&mut stack) {
+                        Some(_) => {
+                            paths.push((target, stack.clone()));
+                            Ok(())
+                        }
+                        None => Err(self.underflow(func, &stack, "if", 1)),
+                    },
+                    Instr::Call(args, target) => {
+                        let name = match self.instrs.get(target) {
+                            Some(Instr::Begin(name)) => self.names.get(name).cloned(),
+                            _ => None,
+                        };
+                        let name = name.unwrap_or("function");
+                        let mut res = Ok(());
+                        for _ in 0..args {
+                            if Checker::pop(&mut stack).is_none() {
+                                res = Err(self.underflow(func, &stack, name, args));
+                                break;
+                            }
+                        }
+                        stack.push(Slot::Value(Type::Any));
+                        res
+                    }
+                    Instr::Keyword(sym) => self.keyword(func, &mut stack, sym),
+                    Instr::Return | Instr::End(_) => {
+                        let values = stack.len();
+                        if func.returns && values > 1 && !stack.contains(&Slot::Unknown) {
+                            let reason = format!(
+                                "`{}` leaves {} values but only the last is returned",
+                                func.name, values
+                            );
+                            return Err(self.error(error!(UnusedValues, &reason), start - 1));
+                        }
+                        break;
+                    }
+                    _ => Ok(()),
+                };
+
+                if let Err(err) = res {
+                    return Err(self.error(err, pc));
+                }
+                pc += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that the functions of a program take values from the stack that
+/// are there, of the types that are expected, before it is run
+pub fn check(dirs: &[Directive], instrs: &[Instr]) -> Result<(), Error> {
+    let checker = Checker::new(instrs, dirs);
+
+    for dir in dirs {
+        let (name, args) = match dir.name.data {
+            Name::Def => (
+                dir.arg_at(0)?,
+                ((dir.arg_at(1)?).as_value()?).as_num()? as u64,
+            ),
+            Name::Track => (dir.arg_at(0)?, 0),
+            _ => continue,
+        };
+        let name = match name.as_value()? {
+            Value::Keyword(name) => name,
+            _ => return Err(error!(UnexpectedToken)),
+        };
+
+        let begin = Instr::Begin(hash_str(name));
+        let start = match instrs.iter().position(|instr| *instr == begin) {
+            Some(pc) => pc + 1,
+            None => continue,
+        };
+        let func = Function {
+            name,
+            args,
+            returns: dir.name.data == Name::Def,
+        };
+        let args = vec![Slot::Value(Type::Any); args as usize];
+        checker.function(func, start, args)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{assemble, parser};
+    use super::*;
+    use crate::err::Kind;
+
+    fn check_code(body: &str) -> Result<(), Error> {
+        let code = format!(".version 0\n.def up 1:\n  12 add\n{}", body);
+        let dirs = parser(&code)?;
+        let instrs = assemble(&code, &dirs)?;
+        check(&dirs, &instrs)
+    }
+
+    #[test]
+    fn test_effects_parse() {
+        for (name, doc) in docs() {
+            assert!(parse_effect(doc.effect).is_some(), "{}", name);
+        }
+        assert_eq!(
+            parse_effect("( value:any -- value:any* num )"),
+            Some(Effect {
+                inputs: vec![Param {
+                    name: "value",
+                    ty: Type::Any,
+                    variable: false,
+                }],
+                outputs: vec![
+                    Param {
+                        name: "value",
+                        ty: Type::Any,
+                        variable: true,
+                    },
+                    Param {
+                        name: "",
+                        ty: Type::Num,
+                        variable: false,
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_valid_programs() {
+        let cases = [
+            ".track t1:\n  [60 up] 500 0 midi_out",
+            ".track t1:\n  revision 2 modulo 0 eq if (60 62) else (48 ~) then 200 1 midi_out",
+            ".track t1:\n  (60 62 64) [: 60 gt :] filter 200 1 midi_out",
+            ".track t1:\n  [1 2] 3 repeat 3 add",
+            ".track t1:\n  0 10 range = @seq 0 10 @seq onsets 250 1 midi_out",
+            ".def five 0:\n  [1 2] cycle 5",
+        ];
+        for case in cases.iter() {
+            assert_eq!(check_code(case), Ok(()), "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_underflow() {
+        let err = check_code(".track t1:\n  [60] 500 midi_out").unwrap_err();
+        assert_eq!(err.kind, Kind::StackExhausted);
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 5,
+                col: 11,
+                len: 8
+            })
+        );
+
+        // Values can't be taken from outside of a list
+        let err = check_code(".track t1:\n  1 [add]").unwrap_err();
+        assert_eq!(err.kind, Kind::StackExhausted);
+        assert_eq!(err.hint, None);
+
+        // Or from beyond a function's arguments
+        let err = check_code(".def two 1:\n  add").unwrap_err();
+        assert_eq!(err.kind, Kind::StackExhausted);
+        assert_eq!(err.hint.unwrap(), "`two` is declared with 1 arguments");
+
+        let err = check_code(".track t1:\n  up").unwrap_err();
+        assert_eq!(err.kind, Kind::StackExhausted);
+
+        // Only when a condition is false
+        let err = check_code(".track t1:\n  1 revision if 2 else add then").unwrap_err();
+        assert_eq!(err.kind, Kind::StackExhausted);
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let err = check_code(".track t1:\n  [60] 'fast 0 midi_out").unwrap_err();
+        assert_eq!(err.kind, Kind::InvalidArgs);
+        assert_eq!(
+            err.reason.unwrap(),
+            "`midi_out` expects num for `dur` but finds sym"
+        );
+
+        let err = check_code(".track t1:\n  [1 2] 3 swap rotate").unwrap_err();
+        assert_eq!(err.kind, Kind::InvalidArgs);
+    }
+
+    #[test]
+    fn test_unused_values() {
+        let err = check_code(".def two 0:\n  1 2").unwrap_err();
+        assert_eq!(err.kind, Kind::UnusedValues);
+        assert_eq!(
+            err.span,
+            Some(Span {
+                line: 4,
+                col: 5,
+                len: 3
+            })
+        );
+
+        // Tracks don't return values
+        assert_eq!(check_code(".track t1:\n  1 2"), Ok(()));
+    }
+}
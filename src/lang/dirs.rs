@@ -2,7 +2,7 @@ use std::fmt;
 
 use serde::Serialize;
 
-use crate::err::Error;
+use crate::err::{Error, Span};
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize)]
 pub enum Value<'a> {
@@ -23,6 +23,11 @@ pub enum Symbol<'a> {
     GroupEnd,
     Null,
     Assign(&'a str),
+    If,
+    Else,
+    Then,
+    QuoteBegin,
+    QuoteEnd,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize)]
@@ -43,6 +48,7 @@ pub enum Name {
     Globals,
     Def,
     Track,
+    Import,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize)]
@@ -64,6 +70,16 @@ impl Location {
     }
 }
 
+impl From<Location> for Span {
+    fn from(loc: Location) -> Span {
+        Span {
+            line: loc.line,
+            col: loc.col,
+            len: loc.end - loc.begin,
+        }
+    }
+}
+
 impl Default for Location {
     fn default() -> Location {
         Location::new(1, 0, 0, 0)
@@ -111,6 +127,7 @@ impl fmt::Display for Name {
             Name::Def => write!(f, ".def"),
             Name::Globals => write!(f, ".globals"),
             Name::Track => write!(f, ".track"),
+            Name::Import => write!(f, ".import"),
         }
     }
 }
@@ -138,6 +155,11 @@ impl<'a> fmt::Display for Symbol<'a> {
             Symbol::GroupEnd => write!(f, "}}"),
             Symbol::Null => write!(f, "~"),
             Symbol::Assign(var) => write!(f, "= @{}", var),
+            Symbol::If => write!(f, "if"),
+            Symbol::Else => write!(f, "else"),
+            Symbol::Then => write!(f, "then"),
+            Symbol::QuoteBegin => write!(f, "[:"),
+            Symbol::QuoteEnd => write!(f, ":]"),
         }
     }
 }
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::assem::hash_str;
+use super::dirs::{Argument, Code, Symbol, Value};
+use super::parse::parser;
+use crate::vm::{keywords, Instr};
+
+/// Strings packed into the globals block, by their id
+fn strings(instrs: &[Instr]) -> HashMap<u64, String> {
+    let mut strings = HashMap::new();
+    for (pc, instr) in instrs.iter().enumerate() {
+        if let Instr::StoreString(id, len) = *instr {
+            let bytes: Vec<u8> = instrs[pc + 1..]
+                .iter()
+                .take(len as usize)
+                .filter_map(|instr| match *instr {
+                    Instr::RawData(byte) => Some(byte),
+                    _ => None,
+                })
+                .collect();
+            strings.insert(id, String::from_utf8_lossy(&bytes).into_owned());
+        }
+    }
+    strings
+}
+
+/// Names that instructions could have been hashed from, in the strings of
+/// the program and the sources it was compiled from
+fn names(strings: &HashMap<u64, String>, sources: &[&str]) -> HashMap<u64, String> {
+    let mut names: HashMap<u64, String> = keywords()
+        .into_iter()
+        .map(|name| (hash_str(name), String::from(name)))
+        .collect();
+    // Tokens kept for debugging cover most of the words in a program
+    for text in strings.values() {
+        names.insert(hash_str(text), text.clone());
+    }
+
+    let mut add = |name: &str| {
+        names.insert(hash_str(name), String::from(name));
+    };
+    let mut add_value = |val: Value| match val {
+        Value::Variable(name) | Value::Symbol(name) | Value::Keyword(name) => add(name),
+        _ => (),
+    };
+    let dirs = sources.iter().filter_map(|source| parser(source).ok());
+    for dir in dirs.flatten() {
+        for arg in &dir.args {
+            match *arg {
+                Argument::Arg(val) => add_value(val.data),
+                Argument::Kwarg(key, val) => {
+                    add_value(Value::Variable(key.data));
+                    add_value(val.data);
+                }
+            }
+        }
+        for token in &dir.body {
+            match token.data {
+                Code::Value(val) => add_value(val),
+                Code::Symbol(Symbol::Assign(var)) => add_value(Value::Variable(var)),
+                _ => (),
+            }
+        }
+    }
+    names
+}
+
+struct Disassembler<'a> {
+    instrs: &'a [Instr],
+    strings: HashMap<u64, String>,
+    names: HashMap<u64, String>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn name(&self, hash: u64) -> String {
+        match self.names.get(&hash) {
+            Some(name) => name.clone(),
+            None => format!("#{:016x}", hash),
+        }
+    }
+
+    /// Name of a block, the first two hold the globals and list of tracks
+    fn block(&self, name: u64) -> String {
+        match name {
+            0 => String::from("globals"),
+            1 => String::from("tracks"),
+            name => self.name(name),
+        }
+    }
+
+    fn string(&self, id: u64) -> String {
+        match self.strings.get(&id) {
+            Some(text) => format!("{:?}", text),
+            None => format!("#{}", id),
+        }
+    }
+
+    /// Name of the function starting at an address
+    fn function(&self, pc: usize) -> String {
+        match self.instrs.get(pc) {
+            Some(Instr::Begin(name)) => self.name(*name),
+            _ => format!("<{}>", pc),
+        }
+    }
+
+    fn instr(&self, pc: usize, instr: Instr) -> String {
+        let (op, args) = match instr {
+            Instr::Begin(name) => ("Begin", self.block(name)),
+            Instr::End(name) => ("End", self.block(name)),
+            Instr::Call(args, addr) => ("Call", format!("{} {}", self.function(addr), args)),
+            Instr::Return => ("Return", String::new()),
+            Instr::Jump(addr) => ("Jump", format!("-> {}", addr)),
+            Instr::Branch(addr) => ("Branch", format!("-> {}", addr)),
+            Instr::Quote(len) => ("Quote", format!("-> {}", pc + len)),
+            Instr::LoadNumber(num) => ("LoadNumber", num.to_string()),
+            Instr::LoadSymbol(sym) => ("LoadSymbol", format!("'{}", self.name(sym))),
+            Instr::LoadVar(var) => ("LoadVar", format!("@{}", self.name(var))),
+            Instr::LoadString(id) => ("LoadString", self.string(id)),
+            Instr::StoreString(id, len) => (
+                "StoreString",
+                format!("{} {} ({} bytes)", id, self.string(id), len),
+            ),
+            Instr::RawData(byte) => ("RawData", byte.to_string()),
+            Instr::StoreGlob(var) => ("StoreGlob", format!("@{}", self.name(var))),
+            Instr::StoreVar(var) => ("StoreVar", format!("@{}", self.name(var))),
+            Instr::Keyword(sym) => ("Keyword", self.name(sym)),
+            Instr::ListBegin => ("ListBegin", String::new()),
+            Instr::ListEnd => ("ListEnd", String::new()),
+            Instr::SeqBegin => ("SeqBegin", String::new()),
+            Instr::SeqEnd => ("SeqEnd", String::new()),
+            Instr::GroupBegin => ("GroupBegin", String::new()),
+            Instr::GroupEnd => ("GroupEnd", String::new()),
+            Instr::Null => ("Null", String::new()),
+            Instr::SourceLoc(addr, id, line, col) => (
+                "SourceLoc",
+                format!("{} {} {}:{}", addr, self.string(id), line, col),
+            ),
+        };
+        format!("{:>5}  {:<12}{}", pc, op, args)
+            .trim_end()
+            .to_string()
+    }
+}
+
+/// Describe a program's instructions, one per line, resolving the names of
+/// keywords, variables and functions along with the source they came from.
+/// Functions defined in imported files are named from their sources
+pub fn disassemble(instrs: &[Instr], source: Option<&str>, imports: &[&str]) -> String {
+    let strings = strings(instrs);
+    let mut sources = imports.to_vec();
+    sources.extend(source);
+    let dis = Disassembler {
+        instrs,
+        names: names(&strings, &sources),
+        strings,
+    };
+
+    let lines: HashMap<usize, u64> = instrs
+        .iter()
+        .filter_map(|instr| match *instr {
+            Instr::SourceLoc(pc, _, line, _) => Some((pc as usize, line)),
+            _ => None,
+        })
+        .collect();
+
+    let mut out = String::new();
+    let mut current = None;
+    let mut pc = 0;
+    while pc < instrs.len() {
+        let instr = instrs[pc];
+        if let Instr::Begin(name) = instr {
+            let label = dis.block(name);
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            writeln!(&mut out, "{}:", label).unwrap();
+            current = None;
+        }
+
+        // Annotate the first instruction of each line of source
+        if let Some(&line) = lines.get(&pc) {
+            if current != Some(line) {
+                let text = source
+                    .and_then(|source| source.lines().nth(line.saturating_sub(1) as usize))
+                    .map(str::trim);
+                match text {
+                    Some(text) => writeln!(&mut out, "       ; {}: {}", line, text),
+                    None => writeln!(&mut out, "       ; line {}", line),
+                }
+                .unwrap();
+                current = Some(line);
+            }
+        }
+
+        writeln!(&mut out, "{}", dis.instr(pc, instr)).unwrap();
+        // The bytes of a string are shown decoded rather than one by one
+        if let Instr::StoreString(_, len) = instr {
+            pc += instrs[pc + 1..]
+                .iter()
+                .take(len as usize)
+                .take_while(|instr| matches!(instr, Instr::RawData(_)))
+                .count();
+        }
+        pc += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{assemble, parser};
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let source = ".version 0
+.globals @root = 60
+.def up 1:
+  12 add
+.track t1:
+  [@root up] 500 0 midi_out
+";
+        let instrs = assemble(source, &parser(source).unwrap()).unwrap();
+        let text = disassemble(&instrs, Some(source), &[]);
+        let expected = "up:
+       ; 3: .def up 1:
+    0  Begin       up
+       ; 4: 12 add
+    1  LoadNumber  12
+    2  Keyword     add
+    3  Return
+    4  End         up
+
+t1:
+       ; 5: .track t1:
+    5  Begin       t1
+       ; 6: [@root up] 500 0 midi_out
+    6  ListBegin
+    7  LoadVar     @root
+    8  Call        up 1
+";
+        assert!(text.starts_with(expected), "{}", text);
+        assert!(text.contains("\nglobals:\n"));
+        assert!(text.contains("  LoadNumber  60\n"));
+        assert!(text.contains("  StoreGlob   @root\n"));
+        assert!(text.contains("  SourceLoc   8 \"up\" 6:9\n"));
+        assert!(text.contains("  StoreString 1 \"12\" (2 bytes)\n"));
+        assert!(!text.contains("RawData"));
+        assert!(text.contains("\ntracks:\n   71  Begin       tracks\n"));
+        assert!(text.contains("  LoadSymbol  't1\n"));
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt::Write;
+
+use super::dirs::{Argument, Code, Directive, Name, Symbol, Token, Value};
+use super::parse::parser;
+use crate::err::Error;
+
+const INDENT: &str = "  ";
+
+/// A comment in the source, from the `;` to the end of its line
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Comment<'a> {
+    line: usize,
+    col: usize,
+    text: &'a str,
+    // Whether the comment is the only thing on its line
+    alone: bool,
+}
+
+/// Find the comments in a program, which the parser skips over. As in the
+/// parser, a `;` starts a comment even within a string
+fn comments(source: &str) -> Vec<Comment<'_>> {
+    let mut comments = vec![];
+    for (idx, line) in source.lines().enumerate() {
+        if let Some(pos) = line.find(';') {
+            comments.push(Comment {
+                line: idx + 1,
+                col: line[..pos].chars().count(),
+                text: line[pos..].trim_end(),
+                alone: line[..pos].trim().is_empty(),
+            });
+        }
+    }
+    comments
+}
+
+/// Text of a value, numbers are kept as they were written
+fn value(source: &str, val: &Token<Value>) -> String {
+    match val.data {
+        Value::Number(_) => String::from(&source[val.loc.begin..val.loc.end]),
+        data => data.to_string(),
+    }
+}
+
+fn argument(source: &str, arg: &Argument) -> String {
+    match *arg {
+        Argument::Arg(ref val) => value(source, val),
+        Argument::Kwarg(key, ref val) => format!("@{} = {}", key.data, value(source, val)),
+    }
+}
+
+fn arg_line(arg: &Argument) -> usize {
+    match *arg {
+        Argument::Arg(val) => val.loc.line,
+        Argument::Kwarg(key, _) => key.loc.line,
+    }
+}
+
+/// Change in nesting after a token, along with whether it closes a block
+fn nesting(code: &Code) -> (isize, bool) {
+    match *code {
+        Code::Symbol(Symbol::ListBegin)
+        | Code::Symbol(Symbol::SeqBegin)
+        | Code::Symbol(Symbol::GroupBegin)
+        | Code::Symbol(Symbol::QuoteBegin)
+        | Code::Symbol(Symbol::If) => (1, false),
+        Code::Symbol(Symbol::ListEnd)
+        | Code::Symbol(Symbol::SeqEnd)
+        | Code::Symbol(Symbol::GroupEnd)
+        | Code::Symbol(Symbol::QuoteEnd)
+        | Code::Symbol(Symbol::Then) => (-1, true),
+        Code::Symbol(Symbol::Else) => (0, true),
+        _ => (0, false),
+    }
+}
+
+/// A line of a directive's body, with tokens spaced apart except just
+/// inside of brackets
+fn code_line(source: &str, tokens: &[Token<Code>]) -> String {
+    let mut out = String::new();
+    let mut prev: Option<Code> = None;
+    for token in tokens {
+        let text = match token.data {
+            Code::Value(val) => value(source, &Token::new(val, token.loc)),
+            code => code.to_string(),
+        };
+        let tight = matches!(
+            (prev, token.data),
+            (None, _)
+                | (Some(Code::Symbol(Symbol::ListBegin)), _)
+                | (Some(Code::Symbol(Symbol::SeqBegin)), _)
+                | (Some(Code::Symbol(Symbol::GroupBegin)), _)
+                | (_, Code::Symbol(Symbol::ListEnd))
+                | (_, Code::Symbol(Symbol::SeqEnd))
+                | (_, Code::Symbol(Symbol::GroupEnd))
+        );
+        if !tight {
+            out.push(' ');
+        }
+        out.push_str(&text);
+        prev = Some(token.data);
+    }
+    out
+}
+
+/// Append a comment to the end of a line of code
+fn with_comment(line: String, comment: Option<&Comment>) -> String {
+    match comment {
+        Some(comment) => format!("{} {}", line, comment.text),
+        None => line,
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: Vec<Comment<'a>>,
+    out: String,
+}
+
+impl<'a> Formatter<'a> {
+    fn trailing(&self, line: usize) -> Option<&Comment<'a>> {
+        self.comments.iter().find(|c| c.line == line && !c.alone)
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        writeln!(&mut self.out, "{}{}", INDENT.repeat(depth), text).unwrap();
+    }
+
+    fn header(&mut self, dir: &Directive) {
+        let kwargs = dir
+            .args
+            .iter()
+            .filter(|arg| matches!(arg, Argument::Kwarg(..)))
+            .count();
+        let line = dir.name.loc.line;
+
+        // Global variables are declared one per line, lined up
+        if dir.name.data == Name::Globals && kwargs > 1 {
+            let header = with_comment(dir.name.data.to_string(), self.trailing(line));
+            self.line(0, &header);
+
+            let width = dir
+                .args
+                .iter()
+                .map(|arg| match *arg {
+                    Argument::Kwarg(key, _) => key.data.len(),
+                    Argument::Arg(_) => 0,
+                })
+                .max()
+                .unwrap_or(0);
+            let mut from = line;
+            for (idx, arg) in dir.args.iter().enumerate() {
+                // Comments on lines of their own stay between the variables
+                let at = arg_line(arg);
+                let alone: Vec<Comment> = self
+                    .comments
+                    .iter()
+                    .filter(|c| c.alone && c.line > from && c.line < at)
+                    .cloned()
+                    .collect();
+                for comment in &alone {
+                    self.line(1, comment.text);
+                }
+                from = at;
+
+                let text = match *arg {
+                    Argument::Kwarg(key, ref val) => format!(
+                        "@{:width$} = {}",
+                        key.data,
+                        value(self.source, val),
+                        width = width
+                    ),
+                    Argument::Arg(ref val) => value(self.source, val),
+                };
+                // Comments go after the last variable on their line
+                let comment = match dir.args.get(idx + 1) {
+                    Some(next) if arg_line(next) == at => None,
+                    _ if at == line => None,
+                    _ => self.trailing(at),
+                };
+                self.line(1, &with_comment(text, comment));
+            }
+            return;
+        }
+
+        let mut header = dir.name.data.to_string();
+        for arg in &dir.args {
+            write!(&mut header, " {}", argument(self.source, arg)).unwrap();
+        }
+        if !dir.body.is_empty() {
+            header.push(':');
+        }
+        // Comments after code in the body go with that code instead
+        let last = dir.args.iter().map(arg_line).max().unwrap_or(line);
+        let comment = (line..=last)
+            .filter(|&at| dir.body.iter().all(|token| token.loc.line != at))
+            .filter_map(|at| self.trailing(at))
+            .next();
+        let header = with_comment(header, comment);
+        self.line(0, &header);
+    }
+
+    fn body(&mut self, dir: &Directive, first: usize, last: usize) {
+        let mut depth: isize = 0;
+        let mut previous = None;
+        for at in first..=last {
+            let tokens: Vec<Token<Code>> = dir
+                .body
+                .iter()
+                .filter(|token| token.loc.line == at)
+                .cloned()
+                .collect();
+            let alone = self
+                .comments
+                .iter()
+                .find(|c| c.line == at && c.alone)
+                .cloned();
+
+            if tokens.is_empty() && alone.is_none() {
+                continue;
+            }
+            // Keep paragraphs apart, with a single blank line
+            if let Some(previous) = previous {
+                if at > previous + 1 {
+                    self.out.push('\n');
+                }
+            }
+            previous = Some(at);
+
+            if let Some(comment) = alone {
+                self.line(1 + depth.max(0) as usize, comment.text);
+                continue;
+            }
+
+            let mut closing = 0;
+            for token in &tokens {
+                match nesting(&token.data) {
+                    (_, true) => closing += 1,
+                    _ => break,
+                }
+                if token.data == Code::Symbol(Symbol::Else) {
+                    break;
+                }
+            }
+            let indent = 1 + (depth - closing).max(0) as usize;
+            depth += tokens
+                .iter()
+                .map(|token| nesting(&token.data).0)
+                .sum::<isize>();
+
+            let text = code_line(self.source, &tokens);
+            let comment = self.trailing(at);
+            self.line(indent, &with_comment(text, comment));
+        }
+    }
+}
+
+/// Format a program, normalising the layout of directives and their bodies
+/// while keeping comments and blank lines between paragraphs of code
+pub fn format(source: &str) -> Result<String, Error> {
+    let dirs = parser(source)?;
+    let mut fmt = Formatter {
+        source,
+        comments: comments(source),
+        out: String::new(),
+    };
+
+    // Lines each directive covers, up to the last of its tokens
+    let spans: Vec<(usize, usize)> = dirs
+        .iter()
+        .map(|dir| {
+            let args = dir.args.iter().map(arg_line);
+            let body = dir.body.iter().map(|token| token.loc.line);
+            let last = args.chain(body).max().unwrap_or(dir.name.loc.line);
+            (dir.name.loc.line, last)
+        })
+        .collect();
+
+    let mut previous: Option<(&Directive, usize)> = None;
+    for (idx, dir) in dirs.iter().enumerate() {
+        let (first, mut last) = spans[idx];
+        let next = spans.get(idx + 1).map(|&(next, _)| next);
+
+        // Comments before a directive are kept above it, unless they are
+        // indented under the directive before
+        let from = previous.map(|(_, last)| last + 1).unwrap_or(1);
+        let above: Vec<Comment> = fmt
+            .comments
+            .iter()
+            .filter(|c| c.line >= from && c.line < first)
+            .cloned()
+            .collect();
+
+        if let Some((prev, _)) = previous {
+            // Consecutive directives without bodies, like imports, are
+            // kept together
+            let grouped = prev.name.data == dir.name.data
+                && prev.body.is_empty()
+                && dir.body.is_empty()
+                && above.is_empty();
+            if !grouped {
+                fmt.out.push('\n');
+            }
+        }
+        for comment in &above {
+            fmt.line(0, comment.text);
+        }
+
+        fmt.header(dir);
+        if !dir.body.is_empty() {
+            // Indented comments after the body still belong to it
+            let end = next.unwrap_or(usize::MAX);
+            if let Some(c) = fmt
+                .comments
+                .iter()
+                .rfind(|c| c.line > last && c.line < end && c.alone && c.col > 0)
+            {
+                last = c.line;
+            }
+            let start = dir.body.iter().map(|t| t.loc.line).min().unwrap_or(first);
+            let header = dir.args.iter().map(arg_line).max().unwrap_or(first);
+            fmt.body(dir, start.min(header + 1), last);
+        }
+        previous = Some((dir, last));
+    }
+
+    // Comments after the last directive
+    let from = previous.map(|(_, last)| last + 1).unwrap_or(1);
+    let below: Vec<Comment> = fmt
+        .comments
+        .iter()
+        .filter(|c| c.line >= from)
+        .cloned()
+        .collect();
+    if !below.is_empty() && previous.is_some() {
+        fmt.out.push('\n');
+    }
+    for comment in &below {
+        fmt.line(0, comment.text);
+    }
+
+    Ok(fmt.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = "; Shared patterns
+.version   0
+.globals @root = 60 @scale = 'major
+.def up 1: 12 add  ; an octave
+
+.track t1:
+   [ 60 up ]  ( 1 2 )
+  revision 2 modulo 0 eq if
+  (60 62)
+      else
+  ( 48 ~ )
+    then
+
+
+ [: 1 add :] map 500 0 midi_out
+   ; the end
+";
+        let expected = "; Shared patterns
+.version 0
+
+.globals
+  @root  = 60
+  @scale = 'major
+
+.def up 1:
+  12 add ; an octave
+
+.track t1:
+  [60 up] (1 2)
+  revision 2 modulo 0 eq if
+    (60 62)
+  else
+    (48 ~)
+  then
+
+  [: 1 add :] map 500 0 midi_out
+  ; the end
+";
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+
+        let source =
+            ".globals\n  @root = 60 ; root\n  ; minor by default\n  @scale = 2\n.def a 0:\n  1\n";
+        let expected =
+            ".globals\n  @root  = 60 ; root\n  ; minor by default\n  @scale = 2\n\n.def a 0:\n  1\n";
+        assert_eq!(format(source).unwrap(), expected);
+
+        // Strings can't hold comments, which the parser would skip over
+        assert!(format(".track t1:\n  \"a;b\" 1 ; c\n  60] 500 0 midi_out\n").is_err());
+    }
+
+    #[test]
+    fn test_programs_unchanged() {
+        let programs = [
+            include_str!("../../tests/files/conditional_simple.jez"),
+            include_str!("../../tests/files/quote_map.jez"),
+            include_str!("../../tests/files/sieves_simple.jez"),
+            include_str!("../../tests/files/tempo_simple.jez"),
+        ];
+        for program in programs.iter() {
+            assert_eq!(&format(program).unwrap(), program);
+        }
+    }
+
+    #[test]
+    fn test_imports_grouped() {
+        let source = ".version 0\n.import \"a.jez\"\n.import \"b.jez\"\n.def a 0:\n  1\n";
+        let expected = ".version 0\n\n.import \"a.jez\"\n.import \"b.jez\"\n\n.def a 0:\n  1\n";
+        assert_eq!(format(source).unwrap(), expected);
+    }
+}
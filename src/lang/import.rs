@@ -0,0 +1,210 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::assem::assemble_with;
+use super::check::check;
+use super::dirs::{Directive, Name, Value};
+use super::parse::parser;
+use crate::err::{Error, Span};
+use crate::vm::Instr;
+
+/// A file imported by a program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    /// Name the file was imported by
+    pub name: String,
+    pub path: PathBuf,
+    pub source: String,
+    // Where in the program the file was imported, directly or not
+    site: Span,
+}
+
+/// Point an error in an imported file at where it was imported, keeping
+/// the location in the file in the reason
+fn within(err: Error, name: &str, site: Span) -> Error {
+    let mut reason = format!("In {}", name);
+    if let Some(span) = err.span {
+        reason = format!("{} on line {} at column {}", reason, span.line, span.col);
+    }
+    if let Some(ref text) = err.reason {
+        reason = format!("{}: {}", reason, text);
+    }
+    Error {
+        reason: Some(reason),
+        span: None,
+        ..err
+    }
+    .at(site)
+}
+
+impl Module {
+    pub fn parse(&self) -> Result<Vec<Directive<'_>>, Error> {
+        parser(&self.source).map_err(|err| self.error(err))
+    }
+
+    pub fn error(&self, err: Error) -> Error {
+        within(err, &self.name, self.site)
+    }
+}
+
+/// Finds the files imported by a program, next to the file importing them
+/// and then in each search path
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Resolver {
+    file: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+}
+
+impl Resolver {
+    pub fn new(file: Option<&Path>, paths: &[PathBuf]) -> Resolver {
+        Resolver {
+            file: file.map(PathBuf::from),
+            paths: paths.to_vec(),
+        }
+    }
+
+    /// Directory of the program's file, which relative paths used by the
+    /// program are found from
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.file
+            .as_ref()
+            .and_then(|file| file.parent())
+            .map(PathBuf::from)
+    }
+
+    fn find(&self, from: Option<&Path>, name: &str) -> Option<PathBuf> {
+        let local = match from.and_then(Path::parent) {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        let mut candidates = vec![local];
+        candidates.extend(self.paths.iter().map(|dir| dir.join(name)));
+        candidates
+            .into_iter()
+            .find(|path| path.is_file())
+            .and_then(|path| fs::canonicalize(path).ok())
+    }
+
+    /// Load every file a program imports, directly or through other files,
+    /// each before the files that import it
+    pub fn load(&self, code: &str) -> Result<Vec<Module>, Error> {
+        let file = self
+            .file
+            .as_ref()
+            .and_then(|file| fs::canonicalize(file).ok());
+        let mut visiting: Vec<PathBuf> = file.iter().cloned().collect();
+        let mut modules = vec![];
+        self.visit(file.as_deref(), code, None, &mut visiting, &mut modules)?;
+        Ok(modules)
+    }
+
+    fn visit(
+        &self,
+        from: Option<&Path>,
+        code: &str,
+        site: Option<Span>,
+        visiting: &mut Vec<PathBuf>,
+        modules: &mut Vec<Module>,
+    ) -> Result<(), Error> {
+        for dir in parser(code)? {
+            if dir.name.data != Name::Import {
+                continue;
+            }
+            let arg = dir.arg_at(0).map_err(|err| err.at(dir.name.loc.into()))?;
+            let loc: Span = arg.loc()?.into();
+            let name = match arg.as_value()? {
+                Value::StringLiteral(name) => name,
+                _ => return Err(error!(InvalidArgs, "Expected a file name").at(loc)),
+            };
+
+            let path = match self.find(from, name) {
+                Some(path) => path,
+                None => {
+                    let reason = format!("Can't find `{}`", name);
+                    return Err(error!(Io, &reason).at(loc));
+                }
+            };
+            if visiting.contains(&path) {
+                let reason = format!("`{}` ends up importing itself", name);
+                return Err(error!(CyclicImport, &reason).at(loc));
+            }
+            // Files imported more than once are only defined once
+            if modules.iter().any(|module| module.path == path) {
+                continue;
+            }
+
+            let module = Module {
+                name: String::from(name),
+                path: path.clone(),
+                source: fs::read_to_string(&path).map_err(|err| Error::from(err).at(loc))?,
+                site: site.unwrap_or(loc),
+            };
+            visiting.push(path.clone());
+            self.visit(
+                Some(&path),
+                &module.source,
+                Some(module.site),
+                visiting,
+                modules,
+            )
+            .map_err(|err| within(err, name, loc))?;
+            visiting.pop();
+            modules.push(module);
+        }
+        Ok(())
+    }
+}
+
+/// Assemble a program along with the files it imports, checking the stack
+/// effects of every function
+pub fn compile(code: &str, dirs: &[Directive], modules: &[Module]) -> Result<Vec<Instr>, Error> {
+    let mut imports = vec![];
+    for module in modules {
+        imports.push((module, module.parse()?));
+    }
+
+    let instrs = assemble_with(code, dirs, &imports)?;
+    check(dirs, &instrs)?;
+    for (module, dirs) in &imports {
+        // Tracks of imported files aren't assembled
+        let defs: Vec<Directive> = dirs
+            .iter()
+            .filter(|dir| dir.name.data == Name::Def)
+            .cloned()
+            .collect();
+        check(&defs, &instrs).map_err(|err| module.error(err))?;
+    }
+    Ok(instrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::err::Kind;
+
+    #[test]
+    fn test_within() {
+        let site = Span {
+            line: 3,
+            col: 9,
+            len: 7,
+        };
+        let inner = Span {
+            line: 4,
+            col: 2,
+            len: 3,
+        };
+        let err = error!(StackExhausted, "`add` needs 2 values but finds 0")
+            .at(inner)
+            .hint("`up` is declared with 0 arguments");
+
+        let err = within(err, "lib.jez", site);
+        assert_eq!(err.kind, Kind::StackExhausted);
+        assert_eq!(err.span, Some(site));
+        assert_eq!(
+            err.reason.unwrap(),
+            "In lib.jez on line 4 at column 2: `add` needs 2 values but finds 0"
+        );
+        assert_eq!(err.hint.unwrap(), "`up` is declared with 0 arguments");
+    }
+}
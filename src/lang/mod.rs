@@ -1,7 +1,18 @@
 mod assem;
+mod bytecode;
+mod check;
 mod dirs;
+mod disasm;
+mod fmt;
+mod import;
 mod parse;
 
-pub use self::assem::{assemble, hash_str};
-pub use self::dirs::Directive;
+#[cfg(test)]
+pub use self::assem::assemble;
+pub use self::assem::hash_str;
+pub use self::bytecode::{decode, encode};
+pub use self::dirs::{Argument, Directive, Name, Value};
+pub use self::disasm::disassemble;
+pub use self::fmt::format;
+pub use self::import::{compile, Resolver};
 pub use self::parse::parser;
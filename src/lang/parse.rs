@@ -1,5 +1,3 @@
-use std::fmt::Write;
-
 use super::dirs::{Argument, Code, Directive, Location, Name, Symbol, Token, Value};
 
 use crate::err::Error;
@@ -93,6 +91,14 @@ impl<'a> TokenStream<'a> {
         }
     }
 
+    /// Check if a character immediately follows the current position
+    pub fn adjacent(&self, c: char) -> bool {
+        match self.input.get(self.loc.begin..) {
+            Some(string) => string.starts_with(c),
+            None => false,
+        }
+    }
+
     pub fn expect(&mut self, c: char) -> Result<(), Error> {
         match self.next() {
             Some((tk, _)) => {
@@ -149,6 +155,7 @@ struct Parser<'c, 's: 'c> {
 //           | "globals"       -> globals
 //           | "def"           -> def
 //           | "track"         -> track
+//           | "import"        -> import
 // arg       : (VARIABLE "=" value) | value
 // ?code     : (symbol | value)
 // value     : SIGNED_NUMBER   -> number
@@ -165,6 +172,11 @@ struct Parser<'c, 's: 'c> {
 //           | "}"             -> group_end
 //           | "~"             -> null
 //           | "=" VARIABLE    -> assign
+//           | "if"            -> if
+//           | "else"          -> else
+//           | "then"          -> then
+//           | "[:"            -> quote_begin
+//           | ":]"            -> quote_end
 //
 // WORD      : LETTER ("_" | "#" | LETTER | DIGIT)*
 // VARIABLE  : "@" WORD
@@ -188,11 +200,9 @@ impl<'c, 's: 'c> Parser<'c, 's> {
             match self.parse_directive() {
                 Ok(dir) => dirs.push(dir),
                 Err(err) => {
-                    let line = self.stream.loc.line;
-                    let col = self.stream.loc.col;
-                    let mut msg = String::new();
-                    write!(&mut msg, "Error on line {} at column {}", line, col).ok();
-                    return Err(Error::with(err.kind, &msg));
+                    let loc = self.stream.loc;
+                    let loc = Location::new(loc.line, loc.col, loc.begin, loc.begin);
+                    return Err(err.at(loc.into()));
                 }
             };
         }
@@ -211,6 +221,7 @@ impl<'c, 's: 'c> Parser<'c, 's> {
             "globals" => Name::Globals,
             "def" => Name::Def,
             "track" => Name::Track,
+            "import" => Name::Import,
             _ => return Err(error!(UnexpectedToken)),
         };
 
@@ -256,7 +267,9 @@ impl<'c, 's: 'c> Parser<'c, 's> {
             '"' => {
                 self.stream.next().unwrap(); // "
                 let (string, loc) = self.stream.take_while(|c| c != '"').unwrap();
-                self.stream.next().unwrap(); // "
+                // Strings end on the line they start, before any whitespace or
+                // comment
+                self.stream.expect('"')?;
                 Token::new(Value::StringLiteral(string), loc)
             }
             _ => {
@@ -308,7 +321,13 @@ impl<'c, 's: 'c> Parser<'c, 's> {
         let val = match token {
             '[' => {
                 self.stream.next().unwrap();
-                Token::new(Code::Symbol(Symbol::ListBegin), loc)
+                if self.stream.adjacent(':') {
+                    self.stream.next().unwrap();
+                    let loc = Location::new(loc.line, loc.col, loc.begin, loc.end + 1);
+                    Token::new(Code::Symbol(Symbol::QuoteBegin), loc)
+                } else {
+                    Token::new(Code::Symbol(Symbol::ListBegin), loc)
+                }
             }
             ']' => {
                 self.stream.next().unwrap();
@@ -334,6 +353,15 @@ impl<'c, 's: 'c> Parser<'c, 's> {
                 self.stream.next().unwrap();
                 Token::new(Code::Symbol(Symbol::Null), loc)
             }
+            ':' => {
+                self.stream.next().unwrap();
+                if !self.stream.adjacent(']') {
+                    return Err(error!(UnexpectedToken));
+                }
+                self.stream.next().unwrap();
+                let loc = Location::new(loc.line, loc.col, loc.begin, loc.end + 1);
+                Token::new(Code::Symbol(Symbol::QuoteEnd), loc)
+            }
             '=' => {
                 self.stream.next().unwrap();
                 let var = self.parse_variable()?;
@@ -341,7 +369,12 @@ impl<'c, 's: 'c> Parser<'c, 's> {
             }
             _ => {
                 let val = self.parse_value()?;
-                Token::new(Code::Value(val.data), val.loc)
+                match val.data {
+                    Value::Keyword("if") => Token::new(Code::Symbol(Symbol::If), val.loc),
+                    Value::Keyword("else") => Token::new(Code::Symbol(Symbol::Else), val.loc),
+                    Value::Keyword("then") => Token::new(Code::Symbol(Symbol::Then), val.loc),
+                    _ => Token::new(Code::Value(val.data), val.loc),
+                }
             }
         };
 
@@ -467,6 +500,27 @@ mod tests {
         assert_eq!(loc, Location::new(1, 4, 4, 7));
     }
 
+    #[test]
+    fn test_quotations() {
+        let dirs = parser(".def foo 0: [: 1 [2] :] [ :]").unwrap();
+        let body: Vec<Code> = dirs[0].body.iter().map(|tk| tk.data).collect();
+        assert_eq!(
+            body,
+            vec![
+                Code::Symbol(Symbol::QuoteBegin),
+                Code::Value(Value::Number(1.0)),
+                Code::Symbol(Symbol::ListBegin),
+                Code::Value(Value::Number(2.0)),
+                Code::Symbol(Symbol::ListEnd),
+                Code::Symbol(Symbol::QuoteEnd),
+                Code::Symbol(Symbol::ListBegin),
+                Code::Symbol(Symbol::QuoteEnd),
+            ]
+        );
+        assert_eq!(dirs[0].body[0].loc, Location::new(1, 12, 12, 14));
+        assert!(parser(".def foo 0: [: 1 : ]").is_err());
+    }
+
     #[test]
     fn test_stream_comments() {
         let mut ts = TokenStream::new("foo ; comment \nbar");
@@ -3,11 +3,24 @@ mod err;
 mod api;
 mod capi;
 mod lang;
+mod lsp;
+mod repl;
 mod sinks;
+mod smf;
+mod sources;
 mod vm;
 
-pub use crate::api::{simulate, Machine, Program, Sink};
-pub use crate::capi::jez_simulate;
-pub use crate::err::{Error, Kind, Location};
+pub use crate::api::{
+    simulate, simulate_program, simulate_program_to_smf, simulate_to_smf, simulate_to_smf_with,
+    simulate_with, Machine, Program, Sink, TrackStatus,
+};
+pub use crate::capi::{
+    jez_machine_free, jez_machine_new, jez_machine_set_param, jez_machine_update, jez_simulate,
+};
+pub use crate::err::{Error, Kind, Location, Span};
+pub use crate::lang::{format, Resolver};
+pub use crate::lsp::LanguageServer;
+pub use crate::repl::Repl;
 pub use crate::sinks::{Backend, Device};
-pub use crate::vm::{Command, Status};
+pub use crate::sources::{LinkSession, LinkTime, MidiFollower, MidiTime, OscReceiver};
+pub use crate::vm::{Command, Params, Status, TimeSource};
@@ -0,0 +1,256 @@
+use std::path::Path;
+
+use crate::err::{Error, Span};
+use crate::lang::{compile, parser, Argument, Name, Resolver};
+use crate::vm::{docs, Doc};
+
+/// Zero based line and character offset into a document
+pub type Position = (usize, usize);
+
+/// A function or track defined in a document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Definition {
+    pub kind: Name,
+    pub name: String,
+    pub args: u64,
+    pub span: Span,
+}
+
+impl Definition {
+    pub fn signature(&self) -> String {
+        match self.kind {
+            Name::Def => format!("{} {} {}", self.kind, self.name, self.args),
+            _ => format!("{} {}", self.kind, self.name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Completion {
+    Keyword(&'static str, Doc),
+    Definition(Definition),
+}
+
+fn is_word(chr: char) -> bool {
+    chr.is_ascii_alphanumeric() || chr == '_' || chr == '#' || chr == '-'
+}
+
+fn definitions(text: &str) -> Result<Vec<Definition>, Error> {
+    let mut defs = vec![];
+    for dir in &parser(text)? {
+        if dir.name.data != Name::Def && dir.name.data != Name::Track {
+            continue;
+        }
+        let arg = dir.arg_at(0)?;
+        let arity = match dir.arg_at(1) {
+            Ok(Argument::Arg(val)) => val.data.as_num()? as u64,
+            _ => 0,
+        };
+        defs.push(Definition {
+            kind: dir.name.data,
+            name: String::from(arg.as_value()?.as_keyword()?),
+            args: arity,
+            span: arg.loc()?.into(),
+        });
+    }
+    Ok(defs)
+}
+
+/// An open source file, along with what is known about it
+#[derive(Clone, Debug)]
+pub struct Document {
+    text: String,
+    defs: Vec<Definition>,
+    resolver: Resolver,
+}
+
+impl Document {
+    #[cfg(test)]
+    pub fn new(text: &str) -> Document {
+        Document::open(None, text)
+    }
+
+    /// A document saved at a path, that files are imported relative to
+    pub fn open(path: Option<&Path>, text: &str) -> Document {
+        let mut doc = Document {
+            text: String::new(),
+            defs: vec![],
+            resolver: Resolver::new(path, &[]),
+        };
+        doc.update(text);
+        doc
+    }
+
+    /// Replace the text of the document, definitions are kept from the last
+    /// version that parsed so they can be completed while typing
+    pub fn update(&mut self, text: &str) {
+        self.text = String::from(text);
+        if let Ok(defs) = definitions(text) {
+            self.defs = defs;
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let res = self.resolver.load(&self.text).and_then(|modules| {
+            let dirs = parser(&self.text)?;
+            compile(&self.text, &dirs, &modules)
+        });
+        let err = match res {
+            Ok(_) => return vec![],
+            Err(err) => err,
+        };
+
+        let mut loc = err.span.unwrap_or(Span {
+            line: 1,
+            col: 0,
+            len: 0,
+        });
+        // Underline at least one character so the error can be seen
+        loc.len = loc.len.max(1);
+
+        // The location is given by the range of the diagnostic
+        let mut text = Error {
+            span: None,
+            ..err.clone()
+        }
+        .to_string()
+        .replace('\n', ": ");
+        if let Some(hint) = err.hint {
+            text = format!("{}, {}", text, hint);
+        }
+        vec![Diagnostic {
+            span: loc,
+            message: text,
+        }]
+    }
+
+    /// The word under a position, with the span it covers
+    pub fn word_at(&self, pos: Position) -> Option<(&str, Span)> {
+        let (line, chr) = pos;
+        let text = self.text.split('\n').nth(line)?;
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+
+        let mut start = chr.min(chars.len());
+        while start > 0 && is_word(chars[start - 1].1) {
+            start -= 1;
+        }
+        let mut end = chr.min(chars.len());
+        while end < chars.len() && is_word(chars[end].1) {
+            end += 1;
+        }
+        if start == end {
+            return None;
+        }
+
+        let offset = |idx: usize| chars.get(idx).map(|c| c.0).unwrap_or(text.len());
+        let (from, to) = (offset(start), offset(end));
+        let span = Span {
+            line: line + 1,
+            col: start,
+            len: end - start,
+        };
+        Some((&text[from..to], span))
+    }
+
+    /// Built-in keywords and the functions defined in the document
+    pub fn completions(&self) -> Vec<Completion> {
+        let mut words: Vec<(&'static str, Doc)> = docs().into_iter().collect();
+        words.sort_by_key(|&(name, _)| name);
+
+        let mut items: Vec<Completion> = words
+            .into_iter()
+            .map(|(name, doc)| Completion::Keyword(name, doc))
+            .collect();
+        items.extend(self.defs.iter().cloned().map(Completion::Definition));
+        items
+    }
+
+    /// Markdown describing the word under a position
+    pub fn hover(&self, pos: Position) -> Option<String> {
+        let (word, _) = self.word_at(pos)?;
+        if let Some(def) = self.definition(pos) {
+            return Some(format!("```\n{}\n```", def.signature()));
+        }
+        let doc = docs().get(word).cloned()?;
+        Some(format!(
+            "```\n{} {}\n```\n{}",
+            word, doc.effect, doc.summary
+        ))
+    }
+
+    /// Where the word under a position is defined
+    pub fn definition(&self, pos: Position) -> Option<&Definition> {
+        let (word, _) = self.word_at(pos)?;
+        self.defs.iter().find(|def| def.name == word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = ".version 0
+
+.def up 1:
+  12 add
+
+.track t1:
+  [60 up] 500 0 midi_out
+";
+
+    #[test]
+    fn test_diagnostics() {
+        assert_eq!(Document::new(PROGRAM).diagnostics(), vec![]);
+
+        let diags = Document::new(".version 0\n\n.def up 1:\n  12 :add\n").diagnostics();
+        assert_eq!(diags.len(), 1);
+        assert_eq!((diags[0].span.line, diags[0].span.col), (4, 6));
+        assert_eq!(diags[0].span.len, 1);
+
+        let diags = Document::new(".version 0\n\n.def a 0:\n  1\n\n.def a 0:\n  2\n").diagnostics();
+        assert_eq!(diags.len(), 1);
+        assert_eq!((diags[0].span.line, diags[0].span.col), (6, 5));
+        assert_eq!(diags[0].message, "Duplicate function");
+    }
+
+    #[test]
+    fn test_word_at() {
+        let doc = Document::new(PROGRAM);
+        let (word, span) = doc.word_at((6, 6)).unwrap();
+        assert_eq!(word, "up");
+        assert_eq!((span.line, span.col), (7, 6));
+        assert_eq!(span.len, 2);
+        assert_eq!(doc.word_at((6, 2)), None);
+        assert_eq!(doc.word_at((20, 0)), None);
+    }
+
+    #[test]
+    fn test_hover() {
+        let doc = Document::new(PROGRAM);
+        let hover = doc.hover((3, 6)).unwrap();
+        assert!(hover.contains("add ( lhs:num rhs:num -- num )"));
+        assert_eq!(doc.hover((6, 7)).unwrap(), "```\n.def up 1\n```");
+        assert_eq!(doc.hover((6, 10)), None);
+    }
+
+    #[test]
+    fn test_definition() {
+        let mut doc = Document::new(PROGRAM);
+        let def = doc.definition((6, 6)).cloned().unwrap();
+        assert_eq!((def.span.line, def.span.col), (3, 5));
+
+        // Definitions survive edits that don't parse
+        doc.update(".version 0\n\n.def up 1:\n  12 add\n\n.track t1:\n  [60 up");
+        assert!(doc.definition((6, 6)).is_some());
+        assert!(doc
+            .completions()
+            .iter()
+            .any(|item| item == &Completion::Definition(def.clone())));
+    }
+}
@@ -0,0 +1,316 @@
+mod document;
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::err::{Error, Span};
+
+use self::document::{Completion, Document, Position};
+
+// Completion item kinds from the specification
+const FUNCTION: u64 = 3;
+const KEYWORD: u64 = 14;
+
+fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>, Error> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        if let (Some(name), Some(val)) = (header.next(), header.next()) {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = val.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let len = match len {
+        Some(len) => len,
+        None => return Err(error!(IncompleteInput, "Missing content length")),
+    };
+    let mut buff = vec![0; len];
+    input.read_exact(&mut buff)?;
+    match serde_json::from_slice(&buff) {
+        Ok(msg) => Ok(Some(msg)),
+        Err(_) => Err(error!(UnexpectedToken, "Invalid message")),
+    }
+}
+
+fn write_message<W: Write>(output: &mut W, msg: &Value) -> Result<(), Error> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn range(span: Span) -> Value {
+    let line = span.line.saturating_sub(1);
+    json!({
+        "start": {"line": line, "character": span.col},
+        "end": {"line": line, "character": span.col + span.len},
+    })
+}
+
+fn position(params: &Value) -> Position {
+    let pos = &params["position"];
+    let line = pos["line"].as_u64().unwrap_or(0);
+    let chr = pos["character"].as_u64().unwrap_or(0);
+    (line as usize, chr as usize)
+}
+
+fn completion(item: &Completion) -> Value {
+    match *item {
+        Completion::Keyword(name, doc) => json!({
+            "label": name,
+            "kind": KEYWORD,
+            "detail": doc.effect,
+            "documentation": doc.summary,
+        }),
+        Completion::Definition(ref def) => json!({
+            "label": def.name,
+            "kind": FUNCTION,
+            "detail": def.signature(),
+        }),
+    }
+}
+
+/// A language server for jez programs, speaking JSON-RPC
+///
+/// Documents are synchronised in full on every change, and diagnostics are
+/// published whenever a document changes.
+#[derive(Debug, Default)]
+pub struct LanguageServer {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl LanguageServer {
+    pub fn new() -> LanguageServer {
+        Default::default()
+    }
+
+    /// Serve requests until the client exits or the input is closed
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, output: W) -> Result<(), Error> {
+        let mut input = input;
+        let mut output = output;
+        while let Some(msg) = read_message(&mut input)? {
+            if msg["method"] == "exit" {
+                break;
+            }
+            for reply in self.handle(&msg) {
+                write_message(&mut output, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a message from the client, returning the messages to send back
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+
+        let result = match msg["method"].as_str().unwrap_or("") {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": {},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": {"name": "jez", "version": env!("CARGO_PKG_VERSION")},
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                // Files are imported relative to documents saved locally
+                let path = uri.strip_prefix("file://").map(Path::new);
+                self.documents
+                    .insert(uri.to_string(), Document::open(path, text));
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                let text = changes.and_then(|changes| changes.last());
+                if let (Some(doc), Some(text)) = (
+                    self.documents.get_mut(uri),
+                    text.and_then(|change| change["text"].as_str()),
+                ) {
+                    doc.update(text);
+                }
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/completion" => match self.documents.get(uri) {
+                Some(doc) => {
+                    Value::from(doc.completions().iter().map(completion).collect::<Vec<_>>())
+                }
+                None => Value::Null,
+            },
+            "textDocument/hover" => {
+                let doc = self.documents.get(uri);
+                match doc.and_then(|doc| doc.hover(position(params))) {
+                    Some(text) => json!({"contents": {"kind": "markdown", "value": text}}),
+                    None => Value::Null,
+                }
+            }
+            "textDocument/definition" => {
+                let doc = self.documents.get(uri);
+                match doc.and_then(|doc| doc.definition(position(params))) {
+                    Some(def) => json!({"uri": uri, "range": range(def.span)}),
+                    None => Value::Null,
+                }
+            }
+            method => {
+                // Notifications without a handler are ignored
+                if msg.get("id").is_none() {
+                    return vec![];
+                }
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": msg["id"],
+                    "error": {"code": -32601, "message": format!("Unknown method {}", method)},
+                })];
+            }
+        };
+
+        match msg.get("id") {
+            Some(id) => vec![json!({"jsonrpc": "2.0", "id": id, "result": result})],
+            None => vec![],
+        }
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let diags: Vec<Value> = match self.documents.get(uri) {
+            Some(doc) => doc
+                .diagnostics()
+                .into_iter()
+                .map(|diag| {
+                    json!({
+                        "range": range(diag.span),
+                        "severity": 1,
+                        "source": "jez",
+                        "message": diag.message,
+                    })
+                })
+                .collect(),
+            None => vec![],
+        };
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diags},
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "method": method, "params": params})
+    }
+
+    fn frame(msgs: &[Value]) -> Vec<u8> {
+        let mut buff = vec![];
+        for msg in msgs {
+            write_message(&mut buff, msg).unwrap();
+        }
+        buff
+    }
+
+    #[test]
+    fn test_session() {
+        let open = json!({
+            "textDocument": {"uri": "file:///a.jez", "text": ".version 0\n\n.def up 1:\n  12 add\n"},
+        });
+        let hover = json!({
+            "textDocument": {"uri": "file:///a.jez"},
+            "position": {"line": 3, "character": 5},
+        });
+        let input = frame(&[
+            request(1, "initialize", json!({})),
+            notification("initialized", json!({})),
+            notification("textDocument/didOpen", open),
+            request(2, "textDocument/hover", hover),
+            request(3, "unknown", json!({})),
+            request(4, "shutdown", Value::Null),
+            notification("exit", Value::Null),
+        ]);
+
+        let mut output = vec![];
+        let mut server = LanguageServer::new();
+        server.run(&input[..], &mut output).unwrap();
+
+        let mut output = &output[..];
+        let mut replies = vec![];
+        while let Some(msg) = read_message(&mut output).unwrap() {
+            replies.push(msg);
+        }
+
+        assert_eq!(replies.len(), 5);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(replies[1]["params"]["diagnostics"], json!([]));
+        let hover = replies[2]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.starts_with("```\nadd ( lhs:num rhs:num -- num )"));
+        assert_eq!(replies[3]["error"]["code"], -32601);
+        assert_eq!(replies[4]["result"], Value::Null);
+        assert!(server.shutdown);
+    }
+
+    #[test]
+    fn test_diagnostics_and_definition() {
+        let mut server = LanguageServer::new();
+        let open = json!({
+            "textDocument": {
+                "uri": "file:///a.jez",
+                "text": ".version 0\n\n.def up 1:\n  12 add\n\n.track t1:\n  60 up\n",
+            },
+        });
+        server.handle(&notification("textDocument/didOpen", open));
+
+        let def = server.handle(&request(
+            1,
+            "textDocument/definition",
+            json!({
+                "textDocument": {"uri": "file:///a.jez"},
+                "position": {"line": 6, "character": 5},
+            }),
+        ));
+        assert_eq!(
+            def[0]["result"]["range"]["start"],
+            json!({"line": 2, "character": 5})
+        );
+
+        let change = json!({
+            "textDocument": {"uri": "file:///a.jez"},
+            "contentChanges": [{"text": ".version 0\n\n.def up 1:\n  12 :add\n"}],
+        });
+        let diags = server.handle(&notification("textDocument/didChange", change));
+        let diags = &diags[0]["params"]["diagnostics"];
+        assert_eq!(
+            diags[0]["range"]["start"],
+            json!({"line": 3, "character": 6})
+        );
+        assert_eq!(diags[0]["severity"], 1);
+    }
+}
@@ -1,23 +1,37 @@
 #[macro_use]
 extern crate jez;
 
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
-use std::io::Read;
-use std::sync::mpsc::{channel, Sender};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Sender, TryRecvError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use docopt::Docopt;
 use serde::Deserialize;
 
-use jez::{simulate, Backend, Command, Error, Machine, Program, Sink, Status};
+use jez::{
+    simulate_program, simulate_program_to_smf, simulate_to_smf_with, simulate_with, Backend,
+    Command, Error, LanguageServer, LinkSession, Machine, MidiFollower, OscReceiver, Program, Repl,
+    Resolver, Sink, Status,
+};
 
 const USAGE: &'static str = "
 Jez.
 
 Usage:
   jez [options] info
+  jez [options] repl [<file>]
+  jez lsp
+  jez fmt [--check] [<file>]
+  jez [options] disasm [<file>]
+  jez [options] build <file>
   jez [options] [<file>]
   jez (-h | --help)
   jez --version
@@ -26,13 +40,26 @@ Options:
   -h, --help            Show this screen.
   --version             Show version.
   --verbose             Print more output.
-  --watch               Reload input file on changes.
+  --check               Fail if the file isn't formatted, instead of formatting it.
+  --watch               Reload input file, and the files it imports, on changes.
+  --import-path=PATHS   Search PATHS, separated by ':', for imported files.
+  --hot                 Keep playing through reloads, keeping track timing.
+  --quantize=BARS       Apply hot reloads on the next multiple of BARS, or of
+                        the longest track with 'longest'.
   --simulate            Run as a non-realtime simulation.
   --time=MS             Length of time (in milliseconds) to run for.
+  --format=FORMAT       Simulation output format, json or midi [default: json].
+  -o, --output=FILE     Write simulation or build output to a file.
   --sink=NAME           Specify the output sink(s).
   --udp-host=ADDRESS    UDP host address [default: 127.0.0.1:34254].
   --udp-client=ADDRESS  UDP client address [default: 127.0.0.1:3000].
   --midi-out=DEVICE     Midi output device id.
+  --midi-clock          Send MIDI clock messages.
+  --midi-in=DEVICE      Midi input device id.
+  --follow              Follow MIDI clock and transport from the midi input.
+  --link                Share tempo and start/stop with peers on the network.
+  --quantum=BEATS       Beats to align starts to when linked [default: 4].
+  --osc-in=ADDRESS      Receive OSC control messages on a UDP address.
   --ws-host=ADDRESS     Websocket host address [default: 127.0.0.1:2794].
 
 Sinks:
@@ -51,15 +78,32 @@ struct Args {
     flag_sink: String,
     flag_time: String,
     flag_simulate: bool,
+    flag_format: String,
+    flag_output: String,
     flag_watch: bool,
+    flag_import_path: String,
+    flag_hot: bool,
+    flag_quantize: Option<String>,
     flag_verbose: bool,
     flag_version: bool,
     flag_udp_host: String,
     flag_udp_client: String,
     flag_midi_out: Option<usize>,
+    flag_midi_clock: bool,
+    flag_midi_in: Option<usize>,
+    flag_follow: bool,
+    flag_link: bool,
+    flag_quantum: f64,
+    flag_osc_in: Option<String>,
     flag_ws_host: String,
     arg_file: String,
     cmd_info: bool,
+    cmd_repl: bool,
+    cmd_lsp: bool,
+    cmd_fmt: bool,
+    cmd_disasm: bool,
+    cmd_build: bool,
+    flag_check: bool,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -73,27 +117,40 @@ type Task = Box<dyn FnMut() -> Result<TaskStatus, Error> + Send>;
 fn watcher_task(
     filepath: String,
     program: Program,
+    resolver: Resolver,
     channel: Sender<Command>,
 ) -> Result<Task, Error> {
-    let meta_data = fs::metadata(filepath.clone())?;
-    let mod_time = meta_data.modified()?;
+    // Imported files are watched along with the program
+    let mut files = vec![PathBuf::from(&filepath)];
+    files.extend(program.imports().iter().cloned());
+    let mod_times = |files: &[PathBuf]| -> Result<Vec<_>, Error> {
+        let mut times = vec![];
+        for path in files {
+            times.push(fs::metadata(path)?.modified()?);
+        }
+        Ok(times)
+    };
+    let mut mod_time = mod_times(&files)?;
 
     Ok(Box::new(move || {
-        let new_meta_data = fs::metadata(filepath.clone())?;
-        let new_mod_time = new_meta_data.modified()?;
-
-        if new_mod_time != mod_time {
-            let mut txt = String::new();
-            let mut fp = fs::File::open(filepath.clone())?;
-            fp.read_to_string(&mut txt)?;
+        // Files can be missing or invalid part way through being edited, and
+        // are checked again once they are next saved
+        let new_mod_time = match mod_times(&files) {
+            Ok(times) => times,
+            Err(_) => return Ok(TaskStatus::Continue),
+        };
+        if new_mod_time == mod_time {
+            return Ok(TaskStatus::Continue);
+        }
+        mod_time = new_mod_time;
 
-            if program != Program::new(txt.as_str())? {
+        match load_program(&filepath, &resolver) {
+            Ok(next) if next != program => {
                 channel.send(Command::Reload).unwrap();
-                return Ok(TaskStatus::Completed);
+                Ok(TaskStatus::Completed)
             }
+            _ => Ok(TaskStatus::Continue),
         }
-
-        Ok(TaskStatus::Continue)
     }))
 }
 
@@ -143,9 +200,87 @@ fn read_program(file_path: &str) -> Result<String, Error> {
     Ok(txt)
 }
 
+fn is_compiled(file_path: &str) -> bool {
+    Path::new(file_path).extension() == Some(OsStr::new("jezc"))
+}
+
+/// Load a program from its source, or as compiled by `jez build`
+fn load_program(file_path: &str, resolver: &Resolver) -> Result<Program, Error> {
+    if is_compiled(file_path) {
+        return Program::from_bytes_with(&fs::read(file_path)?, resolver);
+    }
+    let txt = read_program(file_path)?;
+    Program::load(&txt, resolver)
+}
+
+fn make_resolver(args: &Args) -> Resolver {
+    let file = if args.arg_file.is_empty() {
+        None
+    } else {
+        Some(Path::new(&args.arg_file))
+    };
+    let paths: Vec<PathBuf> = env::split_paths(&args.flag_import_path)
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect();
+    Resolver::new(file, &paths)
+}
+
+fn write_output(file_path: &str, data: &[u8]) -> Result<(), Error> {
+    if file_path.is_empty() {
+        io::stdout().write_all(data)?;
+    } else {
+        let mut fp = fs::File::create(file_path)?;
+        fp.write_all(data)?;
+    }
+    Ok(())
+}
+
 fn run_app(args: &Args) -> Result<(), Error> {
+    if args.cmd_lsp {
+        let stdin = io::stdin();
+        return LanguageServer::new().run(stdin.lock(), io::stdout());
+    }
+
+    if args.cmd_fmt {
+        return run_fmt(args);
+    }
+
+    let resolver = make_resolver(args);
+
+    if args.cmd_disasm {
+        // Compiled programs are listed without their source
+        let (program, source) = if is_compiled(&args.arg_file) {
+            (load_program(&args.arg_file, &resolver)?, None)
+        } else {
+            let txt = read_program(&args.arg_file)?;
+            (Program::load(&txt, &resolver)?, Some(txt))
+        };
+        print!("{}", program.disassemble(source.as_deref()));
+        return Ok(());
+    }
+
+    if args.cmd_build {
+        let program = load_program(&args.arg_file, &resolver)?;
+        let output = if args.flag_output.is_empty() {
+            Path::new(&args.arg_file).with_extension("jezc")
+        } else {
+            PathBuf::from(&args.flag_output)
+        };
+        fs::write(output, program.to_bytes())?;
+        return Ok(());
+    }
+
     if args.flag_simulate {
-        let txt = read_program(&args.arg_file)?;
+        // Compiled programs are simulated without their source
+        let program = if is_compiled(&args.arg_file) {
+            Some(load_program(&args.arg_file, &resolver)?)
+        } else {
+            None
+        };
+        let txt = match program {
+            Some(_) => String::new(),
+            None => read_program(&args.arg_file)?,
+        };
         let dur = if args.flag_time.is_empty() {
             60000.0
         } else {
@@ -154,8 +289,23 @@ fn run_app(args: &Args) -> Result<(), Error> {
                 Err(_) => return Err(error!(InvalidArgs, "Invalid time")),
             }
         };
-        let data = simulate(dur, 0.5, &txt)?;
-        println!("{}", data);
+        let data = match args.flag_format.as_str() {
+            "json" => {
+                let data = match program {
+                    Some(ref program) => simulate_program(dur, 0.5, program)?,
+                    None => simulate_with(dur, 0.5, &txt, &resolver)?,
+                };
+                let mut data = data.into_bytes();
+                data.push(b'\n');
+                data
+            }
+            "midi" => match program {
+                Some(ref program) => simulate_program_to_smf(dur, 0.5, program)?,
+                None => simulate_to_smf_with(dur, 0.5, &txt, &resolver)?,
+            },
+            _ => return Err(error!(InvalidArgs, "Unknown format")),
+        };
+        write_output(&args.flag_output, &data)?;
         return Ok(());
     }
 
@@ -173,9 +323,39 @@ fn run_app(args: &Args) -> Result<(), Error> {
     let (sink_send, sink_recv) = channel();
     sink.run_forever(sink_recv);
 
+    if args.cmd_repl {
+        return run_repl(args, resolver, sink_send);
+    }
+
+    if args.flag_follow && args.flag_link {
+        return Err(error!(
+            InvalidArgs,
+            "Cannot follow MIDI clock and link at the same time"
+        ));
+    }
+
+    if args.flag_quantize.is_some() && !args.flag_hot {
+        return Err(error!(InvalidArgs, "Quantized reloads need --hot"));
+    }
+
+    // Joined once the first program has set its tempo, the machines' sinks
+    // share the session to pass on their tempo changes
+    let link: Rc<RefCell<Option<LinkSession>>> = Rc::new(RefCell::new(None));
+    let follower = if args.flag_follow {
+        Some(MidiFollower::listen(args.flag_midi_in)?)
+    } else {
+        None
+    };
+
+    // Control messages arrive on a channel that outlives each reload
+    let (osc_send, osc_recv) = channel();
+    if let Some(ref addr) = args.flag_osc_in {
+        OscReceiver::new(addr)?.run_forever(osc_send);
+    }
+    let osc_recv = Rc::new(osc_recv);
+
     loop {
-        let txt = read_program(&args.arg_file)?;
-        let program = Program::new(txt.as_str())?;
+        let mut program = load_program(&args.arg_file, &resolver)?;
 
         let (host_to_mach_send, host_to_mach_recv) = channel();
 
@@ -184,18 +364,61 @@ fn run_app(args: &Args) -> Result<(), Error> {
             let task = watcher_task(
                 args.arg_file.clone(),
                 program.clone(),
+                resolver.clone(),
                 host_to_mach_send.clone(),
             )?;
             tasks.push(task);
         }
 
         let mach_to_sink_send = sink_send.clone();
+        let mach_to_link = link.clone();
+        let osc_to_mach_recv = osc_recv.clone();
         let mut machine = Machine::new(
             &program,
-            Box::new(move || host_to_mach_recv.try_recv().ok()),
-            Box::new(move |cmd| mach_to_sink_send.send(cmd).unwrap_or(())),
+            Box::new(move || {
+                host_to_mach_recv
+                    .try_recv()
+                    .ok()
+                    .or_else(|| osc_to_mach_recv.try_recv().ok())
+            }),
+            Box::new(move |cmd| {
+                if let (Command::Tempo(tempo), Some(link)) = (cmd, &*mach_to_link.borrow()) {
+                    link.set_tempo(tempo);
+                }
+                mach_to_sink_send.send(cmd).unwrap_or(())
+            }),
         )?;
 
+        if args.flag_midi_clock {
+            machine.midi_clock();
+        }
+
+        if args.flag_hot {
+            machine.hot_reload();
+            match args.flag_quantize.as_deref() {
+                Some("longest") => machine.quantize_reload_longest(),
+                Some(bars) => match bars.parse::<f64>() {
+                    Ok(bars) => machine.quantize_reload(bars),
+                    Err(_) => return Err(error!(InvalidArgs, "Invalid quantize")),
+                },
+                None => (),
+            }
+        }
+
+        if let Some(ref follower) = follower {
+            machine.follow(Box::new(follower.time_source(host_to_mach_send.clone())));
+        }
+
+        if args.flag_link {
+            if link.borrow().is_none() {
+                *link.borrow_mut() = Some(LinkSession::join(machine.tempo())?);
+            }
+            if let Some(ref link) = *link.borrow() {
+                let channel = host_to_mach_send.clone();
+                machine.follow(Box::new(link.time_source(channel, args.flag_quantum)));
+            }
+        }
+
         if !args.flag_time.is_empty() {
             match args.flag_time.parse::<f64>() {
                 Ok(time) => machine.schedule(time, Command::Stop),
@@ -207,10 +430,47 @@ fn run_app(args: &Args) -> Result<(), Error> {
             thread::spawn(move || run_until_first(tasks));
         }
 
-        match machine.run_forever()? {
-            Status::Stop => return Ok(()),
-            Status::Reload | Status::Continue => (),
-        };
+        loop {
+            match machine.run_forever()? {
+                Status::Stop => {
+                    if let Some(ref link) = *link.borrow() {
+                        link.stop();
+                    }
+                    return Ok(());
+                }
+                Status::Reload if args.flag_hot => {
+                    // Errors in the new program are reported, and the running
+                    // program keeps playing
+                    let next = load_program(&args.arg_file, &resolver)
+                        .and_then(|next| machine.reload(&next).map(|_| next));
+                    match next {
+                        Ok(next) => {
+                            program = next;
+                            if args.flag_verbose {
+                                println!("Reloading {}", args.arg_file);
+                            }
+                        }
+                        Err(err) => report(&args.arg_file, &err),
+                    }
+
+                    if args.flag_watch && !args.arg_file.is_empty() {
+                        let task = watcher_task(
+                            args.arg_file.clone(),
+                            program.clone(),
+                            resolver.clone(),
+                            host_to_mach_send.clone(),
+                        );
+                        match task {
+                            Ok(task) => {
+                                thread::spawn(move || run_until_first(vec![task]));
+                            }
+                            Err(err) => report(&args.arg_file, &err),
+                        }
+                    }
+                }
+                Status::Reload | Status::Continue => break,
+            };
+        }
 
         if args.flag_verbose {
             println!("Reloading {}", args.arg_file);
@@ -218,6 +478,112 @@ fn run_app(args: &Args) -> Result<(), Error> {
     }
 }
 
+fn run_fmt(args: &Args) -> Result<(), Error> {
+    let txt = read_program(&args.arg_file)?;
+    let formatted = jez::format(&txt)?;
+
+    if args.flag_check {
+        if formatted != txt {
+            let name = if args.arg_file.is_empty() {
+                "<stdin>"
+            } else {
+                &args.arg_file
+            };
+            return Err(error!(InvalidArgs, &format!("{} isn't formatted", name)));
+        }
+        return Ok(());
+    }
+
+    // Files are formatted in place, input from stdin goes to stdout
+    if args.arg_file.is_empty() {
+        print!("{}", formatted);
+    } else if formatted != txt {
+        fs::write(&args.arg_file, formatted)?;
+    }
+    Ok(())
+}
+
+fn run_repl(args: &Args, resolver: Resolver, sink_send: Sender<Command>) -> Result<(), Error> {
+    let txt = if args.arg_file.is_empty() {
+        // Programs need at least one function with a body
+        String::from(".version 0\n\n.def init 0:\n  ~\n")
+    } else {
+        read_program(&args.arg_file)?
+    };
+
+    let (osc_send, osc_recv) = channel();
+    if let Some(ref addr) = args.flag_osc_in {
+        OscReceiver::new(addr)?.run_forever(osc_send);
+    }
+
+    let mut machine = Machine::new(
+        &Program::load(txt.as_str(), &resolver)?,
+        Box::new(move || osc_recv.try_recv().ok()),
+        Box::new(move |cmd| sink_send.send(cmd).unwrap_or(())),
+    )?;
+    machine.hot_reload();
+    if args.flag_midi_clock {
+        machine.midi_clock();
+    }
+
+    // Lines are read on their own thread so the machine keeps time
+    let (line_send, line_recv) = channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut line = String::new();
+        while let Ok(len) = stdin.read_line(&mut line) {
+            if len == 0 || line_send.send(line.clone()).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    });
+
+    let mut repl = Repl::with_resolver(&txt, resolver.clone());
+    let prompt = || {
+        print!("> ");
+        io::stdout().flush().unwrap_or(());
+    };
+    prompt();
+
+    let res = Duration::new(0, 1_000_000); // 1ms
+    let mut previous = Instant::now();
+    loop {
+        loop {
+            let line = match line_recv.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            };
+            match repl.handle(&mut machine, &line) {
+                Ok(Some(ref out)) if out.is_empty() => (),
+                Ok(Some(out)) => println!("{}", out),
+                Ok(None) => return Ok(()),
+                Err(err) => eprintln!("{}", err),
+            };
+            prompt();
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(previous);
+        previous = now;
+        let delta = elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1e6;
+
+        match machine.update(delta)? {
+            Status::Stop => return Ok(()),
+            Status::Reload => machine.reload(&Program::load(repl.source(), &resolver)?)?,
+            Status::Continue => (),
+        };
+        thread::sleep(res);
+    }
+}
+
+/// Print an error, showing where in the program it was found
+fn report(file_path: &str, err: &Error) {
+    let source = fs::read_to_string(file_path).unwrap_or_default();
+    eprintln!("{}", err.render(&source));
+}
+
 fn main() {
     let args: Args = Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
@@ -231,7 +597,7 @@ fn main() {
     let code = match run_app(&args) {
         Ok(_) => 0,
         Err(err) => {
-            eprintln!("{}", err);
+            report(&args.arg_file, &err);
             1
         }
     };
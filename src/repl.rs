@@ -0,0 +1,301 @@
+use std::fmt::Write;
+
+use crate::api::{Machine, Program};
+use crate::err::Error;
+use crate::lang::{compile, hash_str, parser, Argument, Directive, Name, Resolver, Value};
+use crate::vm::evaluate;
+
+// Name of the function snippets are evaluated in
+const EVAL: &str = "repl_eval";
+
+const HELP: &str = "\
+<code>              Evaluate code and print the stack
+.def/.track ...     Define or redefine a function or track
+.globals ...        Replace the global variables
+:tracks             List tracks and their latest revision
+:globals            Show the values of global variables
+:tempo BPM          Change the tempo
+:mute/:unmute NAME  Mute or unmute a track
+:solo/:unsolo NAME  Solo or unsolo a track
+:stop/:start NAME   Stop or restart a track
+:source             Print the program
+:help               Print this help
+:quit               Stop the machine and exit";
+
+/// Identifies a directive within a program, functions and tracks by name
+/// and imports by file
+fn key(dir: &Directive) -> (Name, Option<String>) {
+    let name = match dir.name.data {
+        Name::Def | Name::Track => dir
+            .arg_at(0)
+            .and_then(|arg| arg.as_value())
+            .and_then(|val| val.as_keyword().map(String::from))
+            .ok(),
+        Name::Import => match dir.arg_at(0).and_then(|arg| arg.as_value()) {
+            Ok(Value::StringLiteral(path)) => Some(String::from(path)),
+            _ => None,
+        },
+        Name::Version | Name::Globals => None,
+    };
+    (dir.name.data, name)
+}
+
+/// A live console for a running machine
+///
+/// Lines starting with a directive redefine that part of the program and
+/// swap it into the machine. Lines starting with `:` are commands, anything
+/// else is evaluated with the program's functions, and its globals as they
+/// are in the running machine.
+#[derive(Clone, Debug)]
+pub struct Repl {
+    source: String,
+    resolver: Resolver,
+}
+
+impl Repl {
+    pub fn new(source: &str) -> Repl {
+        Repl::with_resolver(source, Resolver::default())
+    }
+
+    /// A console for a program that imports files found by a resolver
+    pub fn with_resolver(source: &str, resolver: Resolver) -> Repl {
+        Repl {
+            source: String::from(source),
+            resolver,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Handle a line of input, returning text to print or `None` when the
+    /// console should exit
+    pub fn handle(&mut self, machine: &mut Machine, line: &str) -> Result<Option<String>, Error> {
+        let line = line.trim();
+        let out = if line.is_empty() {
+            String::new()
+        } else if line.starts_with('.') {
+            self.redefine(machine, line)?
+        } else if let Some(cmd) = line.strip_prefix(':') {
+            let mut words = cmd.split_whitespace();
+            let cmd = words.next().unwrap_or("");
+            let arg = words.next().unwrap_or("");
+            match (cmd, arg) {
+                ("quit", _) => return Ok(None),
+                ("help", _) => String::from(HELP),
+                ("source", _) => self.source.clone(),
+                ("tracks", _) => self.tracks(machine)?,
+                ("globals", _) => self.globals(machine)?,
+                ("tempo", bpm) => match bpm.parse::<f64>() {
+                    Ok(bpm) if bpm > 0.0 => {
+                        machine.set_tempo(bpm);
+                        String::new()
+                    }
+                    _ => return Err(error!(InvalidArgs, "Invalid tempo")),
+                },
+                ("mute", name) if !name.is_empty() => track(machine.mute(name))?,
+                ("unmute", name) if !name.is_empty() => track(machine.unmute(name))?,
+                ("solo", name) if !name.is_empty() => track(machine.solo(name))?,
+                ("unsolo", name) if !name.is_empty() => track(machine.unsolo(name))?,
+                ("stop", name) if !name.is_empty() => track(machine.stop_track(name))?,
+                ("start", name) if !name.is_empty() => track(machine.start_track(name))?,
+                _ => return Err(error!(UnknownKeyword, line)),
+            }
+        } else {
+            self.eval(machine, line)?.join(" ")
+        };
+        Ok(Some(out))
+    }
+
+    /// Evaluate code with the functions of the program and the globals of
+    /// the running machine, returning the values left on the stack
+    pub fn eval(&self, machine: &mut Machine, code: &str) -> Result<Vec<String>, Error> {
+        let mut source = self.source.clone();
+        write!(&mut source, "\n.def {} 0:\n  [ {} ]\n", EVAL, code).unwrap();
+        let modules = self.resolver.load(&source)?;
+        let dirs = parser(&source)?;
+        let instrs = compile(&source, &dirs, &modules)?;
+        let dir = self.resolver.dir();
+        evaluate(&instrs, hash_str(EVAL), &machine.globals(), dir.as_deref())
+    }
+
+    /// Replace directives with the same name, adding any that are new, then
+    /// swap the program into the machine
+    fn redefine(&mut self, machine: &mut Machine, code: &str) -> Result<String, Error> {
+        let mut source = self.source.clone();
+        let mut out = String::new();
+
+        for new in &parser(code)? {
+            let dirs = parser(&source)?;
+            let starts: Vec<usize> = dirs.iter().map(|dir| dir.name.loc.begin - 1).collect();
+            let text = new.to_string();
+
+            match dirs.iter().position(|dir| key(dir) == key(new)) {
+                Some(idx) => {
+                    let end = starts.get(idx + 1).cloned().unwrap_or(source.len());
+                    source.replace_range(starts[idx]..end, &text);
+                    write!(&mut out, "Redefined").unwrap();
+                }
+                None => {
+                    if !source.ends_with('\n') {
+                        source.push('\n');
+                    }
+                    source.push('\n');
+                    source.push_str(&text);
+                    write!(&mut out, "Defined").unwrap();
+                }
+            };
+            match key(new) {
+                (name, Some(word)) => writeln!(&mut out, " {} {}", name, word).unwrap(),
+                (name, None) => writeln!(&mut out, " {}", name).unwrap(),
+            };
+        }
+
+        machine.reload(&Program::load(&source, &self.resolver)?)?;
+        self.source = source;
+        Ok(String::from(out.trim_end()))
+    }
+
+    fn directives<F>(&self, kind: Name, mut each: F) -> Result<(), Error>
+    where
+        F: FnMut(&Directive) -> Result<(), Error>,
+    {
+        for dir in parser(&self.source)?
+            .iter()
+            .filter(|dir| dir.name.data == kind)
+        {
+            each(dir)?;
+        }
+        Ok(())
+    }
+
+    fn tracks(&self, machine: &mut Machine) -> Result<String, Error> {
+        let mut out = String::new();
+        self.directives(Name::Track, |dir| {
+            let name = match key(dir) {
+                (_, Some(name)) => name,
+                (_, None) => return Ok(()),
+            };
+            if let Some(status) = machine.track(&name) {
+                write!(&mut out, "{} revision {}", name, status.revision).unwrap();
+                for &(flag, label) in &[
+                    (status.muted, "muted"),
+                    (status.soloed, "soloed"),
+                    (status.stopped, "stopped"),
+                ] {
+                    if flag {
+                        write!(&mut out, " {}", label).unwrap();
+                    }
+                }
+                writeln!(&mut out).unwrap();
+            }
+            Ok(())
+        })?;
+        Ok(String::from(out.trim_end()))
+    }
+
+    fn globals(&self, machine: &mut Machine) -> Result<String, Error> {
+        let mut out = String::new();
+        self.directives(Name::Globals, |dir| {
+            for arg in &dir.args {
+                let name = match *arg {
+                    Argument::Kwarg(key, _) => key.data,
+                    Argument::Arg(_) => continue,
+                };
+                if let Some(val) = machine.global(name) {
+                    writeln!(&mut out, "@{} = {}", name, val).unwrap();
+                }
+            }
+            Ok(())
+        })?;
+        Ok(String::from(out.trim_end()))
+    }
+}
+
+fn track(res: Result<(), Error>) -> Result<String, Error> {
+    res.map(|_| String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Command;
+
+    const PROGRAM: &str = ".version 0
+
+.globals @root = 60
+
+.def up 1:
+  12 add
+
+.track t1:
+  [@root] 500 0 midi_out
+";
+
+    fn machine() -> Machine {
+        let program = Program::new(PROGRAM).unwrap();
+        let mut machine = Machine::new(&program, Box::new(|| None), Box::new(|_| ())).unwrap();
+        machine.hot_reload();
+        machine
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut machine = machine();
+        let repl = Repl::new(PROGRAM);
+        assert_eq!(repl.eval(&mut machine, "1 2").unwrap(), vec!["1", "2"]);
+        assert_eq!(repl.eval(&mut machine, "@root up").unwrap(), vec!["72"]);
+        assert_eq!(
+            repl.eval(&mut machine, "[1 2] (3 ~)").unwrap(),
+            vec!["[1 2]", "(3 ~)"]
+        );
+
+        // Globals changed while the machine runs are seen by snippets
+        machine.schedule(0.0, Command::SetGlobal(hash_str("root"), 48.0));
+        // Scheduled commands are processed on the update after they fall due
+        for _ in 0..2 {
+            machine.update(1.0).unwrap();
+        }
+        assert_eq!(repl.eval(&mut machine, "@root up").unwrap(), vec!["60"]);
+    }
+
+    #[test]
+    fn test_redefine() {
+        let mut machine = machine();
+        let mut repl = Repl::new(PROGRAM);
+
+        let out = repl.handle(&mut machine, ".def up 1: 7 add").unwrap();
+        assert_eq!(out, Some(String::from("Redefined .def up")));
+        assert_eq!(repl.eval(&mut machine, "@root up").unwrap(), vec!["67"]);
+
+        let out = repl.handle(&mut machine, ".track t2: [72] 500 1 midi_out");
+        assert_eq!(out.unwrap(), Some(String::from("Defined .track t2")));
+        assert_eq!(repl.source().matches(".track").count(), 2);
+        assert_eq!(repl.source().matches(".def up").count(), 1);
+
+        // Invalid programs are not swapped in
+        assert!(repl.handle(&mut machine, ".def up 1: 1 if").is_err());
+        assert_eq!(repl.eval(&mut machine, "@root up").unwrap(), vec!["67"]);
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut machine = machine();
+        let mut repl = Repl::new(PROGRAM);
+
+        assert_eq!(
+            repl.handle(&mut machine, ":mute t1").unwrap(),
+            Some(String::new())
+        );
+        let out = repl.handle(&mut machine, ":tracks").unwrap();
+        assert_eq!(out, Some(String::from("t1 revision 0 muted")));
+
+        let out = repl.handle(&mut machine, ":globals").unwrap();
+        assert_eq!(out, Some(String::from("@root = 60")));
+
+        assert!(repl.handle(&mut machine, ":tempo fast").is_err());
+        assert!(repl.handle(&mut machine, ":unknown").is_err());
+        assert_eq!(repl.handle(&mut machine, ":quit").unwrap(), None);
+    }
+}
@@ -34,6 +34,18 @@ pub fn encode(cmd: Command) -> Option<Vec<u8>> {
             }))
             .unwrap(),
         ),
+        Command::MidiClock => Some(encode_message("/clock")),
+        Command::MidiStart => Some(encode_message("/start")),
+        Command::MidiContinue => Some(encode_message("/continue")),
+        Command::MidiStop => Some(encode_message("/stop")),
         _ => None,
     }
 }
+
+fn encode_message(addr: &str) -> Vec<u8> {
+    encoder::encode(&OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args: vec![],
+    }))
+    .unwrap()
+}
@@ -13,6 +13,14 @@ impl From<pm::Error> for Error {
     }
 }
 
+fn realtime_message(status: u8) -> pm::MidiMessage {
+    pm::MidiMessage {
+        status: status,
+        data1: 0,
+        data2: 0,
+    }
+}
+
 pub struct Portmidi {
     ctx: pm::PortMidi,
     port: Option<pm::OutputPort>,
@@ -90,6 +98,10 @@ impl Sink for Portmidi {
                 data1: ctl,
                 data2: val,
             },
+            Command::MidiClock => realtime_message(248),
+            Command::MidiStart => realtime_message(250),
+            Command::MidiContinue => realtime_message(251),
+            Command::MidiStop => realtime_message(252),
             _ => return,
         };
 
@@ -0,0 +1,8 @@
+mod reader;
+mod writer;
+
+pub use self::reader::{read, Note};
+pub use self::writer::write;
+
+/// Ticks per quarter note
+pub const PPQ: u16 = 480;
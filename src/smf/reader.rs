@@ -0,0 +1,205 @@
+use crate::err::Error;
+
+/// A note, with the tick it starts at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Note {
+    pub tick: u64,
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn invalid() -> Error {
+    error!(InvalidArgs, "Invalid midi file")
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader {
+            data: bytes,
+            pos: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.pos + len > self.data.len() {
+            return Err(invalid());
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        Ok(bytes.iter().fold(0, |val, &b| (val << 8) | u32::from(b)))
+    }
+
+    fn vlq(&mut self) -> Result<u64, Error> {
+        let mut val = 0;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            val = (val << 7) | u64::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                return Ok(val);
+            }
+        }
+        Err(invalid())
+    }
+}
+
+fn read_track(data: &[u8]) -> Result<Vec<Note>, Error> {
+    let mut rdr = Reader::new(data);
+    let mut notes = vec![];
+    let mut ticks = 0;
+    let mut running = None;
+
+    while !rdr.is_empty() {
+        ticks += rdr.vlq()?;
+
+        let mut status = rdr.byte()?;
+        if status < 0x80 {
+            // Running status, the byte belongs to the message
+            status = running.ok_or_else(invalid)?;
+            rdr.pos -= 1;
+        }
+
+        match status {
+            0xff => {
+                rdr.byte()?;
+                let len = rdr.vlq()? as usize;
+                rdr.bytes(len)?;
+            }
+            0xf0 | 0xf7 => {
+                let len = rdr.vlq()? as usize;
+                rdr.bytes(len)?;
+                running = None;
+            }
+            0x80..=0xef => {
+                running = Some(status);
+                let kind = status & 0xf0;
+                let data1 = rdr.byte()?;
+                let data2 = if kind == 0xc0 || kind == 0xd0 {
+                    0
+                } else {
+                    rdr.byte()?
+                };
+                // Note ons with no velocity are note offs
+                if kind == 0x90 && data2 > 0 {
+                    notes.push(Note {
+                        tick: ticks,
+                        pitch: data1,
+                        velocity: data2,
+                    });
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(notes)
+}
+
+/// Read the notes of each track in a standard midi file
+pub fn read(data: &[u8]) -> Result<Vec<Vec<Note>>, Error> {
+    let mut rdr = Reader::new(data);
+    if rdr.bytes(4)? != b"MThd" {
+        return Err(invalid());
+    }
+    let len = rdr.u32()? as usize;
+    rdr.bytes(len)?;
+
+    let mut tracks = vec![];
+    while !rdr.is_empty() {
+        let id = rdr.bytes(4)?;
+        let len = rdr.u32()? as usize;
+        let chunk = rdr.bytes(len)?;
+        // Unknown chunks are to be ignored
+        if id == b"MTrk" {
+            tracks.push(read_track(chunk)?);
+        }
+    }
+
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::write;
+    use super::*;
+    use crate::vm::Command;
+
+    #[test]
+    fn test_read_written() {
+        let commands = vec![
+            (0.0, Command::MidiNoteOn(0, 60, 100)),
+            (0.0, Command::MidiNoteOn(0, 64, 90)),
+            (500.0, Command::MidiNoteOff(0, 60)),
+            (500.0, Command::MidiNoteOff(0, 64)),
+            (500.0, Command::MidiCtl(0, 1, 20)),
+            (500.0, Command::MidiNoteOn(0, 62, 80)),
+            (1000.0, Command::MidiNoteOff(0, 62)),
+        ];
+
+        let tracks = read(&write(120.0, &commands)).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0], []);
+        assert_eq!(
+            tracks[1],
+            [
+                Note {
+                    tick: 0,
+                    pitch: 60,
+                    velocity: 100,
+                },
+                Note {
+                    tick: 0,
+                    pitch: 64,
+                    velocity: 90,
+                },
+                Note {
+                    tick: 480,
+                    pitch: 62,
+                    velocity: 80,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_running_status() {
+        #[rustfmt::skip]
+        let data: &[u8] = &[
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xe0,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 14,
+            0x00, 0x90, 60, 100,
+            0x60, 60, 0,
+            0x00, 62, 100,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+
+        let tracks = read(data).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].len(), 2);
+        assert_eq!(tracks[0][1].tick, 96);
+        assert_eq!(tracks[0][1].pitch, 62);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(read(b"MTrk").is_err());
+        assert!(read(b"MThd\0\0\0\x06\0\0").is_err());
+    }
+}
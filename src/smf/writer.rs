@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::vm::{Command, Destination, EventValue};
+
+use super::PPQ;
+
+type Track = Vec<(u64, Vec<u8>)>;
+type Pending = HashMap<(u8, u8), Vec<usize>>;
+
+/// Converts milliseconds to ticks, following changes in tempo
+struct TempoMap {
+    // Start of each segment in milliseconds and ticks, with its tempo
+    segments: Vec<(f64, f64, f64)>,
+}
+
+impl TempoMap {
+    fn new(tempo: f64) -> TempoMap {
+        TempoMap {
+            segments: vec![(0.0, 0.0, tempo)],
+        }
+    }
+
+    fn position(&self, time: f64) -> f64 {
+        let (start, ticks, tempo) = self
+            .segments
+            .iter()
+            .rev()
+            .find(|seg| seg.0 <= time)
+            .cloned()
+            .unwrap_or(self.segments[0]);
+        ticks + (time - start) * tempo * f64::from(PPQ) / 60000.0
+    }
+
+    fn ticks(&self, time: f64) -> u64 {
+        self.position(time).round() as u64
+    }
+
+    fn change(&mut self, time: f64, tempo: f64) {
+        let ticks = self.position(time);
+        self.segments.push((time, ticks, tempo));
+    }
+}
+
+fn write_vlq(buff: &mut Vec<u8>, val: u64) {
+    let mut val = val;
+    let mut bytes = vec![(val & 0x7f) as u8];
+    val >>= 7;
+    while val > 0 {
+        bytes.push((val & 0x7f) as u8 | 0x80);
+        val >>= 7;
+    }
+    bytes.reverse();
+    buff.extend_from_slice(&bytes);
+}
+
+fn tempo_event(tempo: f64) -> Vec<u8> {
+    let micros = (60_000_000.0 / tempo).round() as u32;
+    vec![
+        0xff,
+        0x51,
+        0x03,
+        (micros >> 16) as u8,
+        (micros >> 8) as u8,
+        micros as u8,
+    ]
+}
+
+fn write_track(buff: &mut Vec<u8>, track: &mut Track) {
+    track.sort_by_key(|evt| evt.0);
+
+    let mut data = vec![];
+    let mut previous = 0;
+    for &(ticks, ref evt) in track.iter() {
+        write_vlq(&mut data, ticks - previous);
+        data.extend_from_slice(evt);
+        previous = ticks;
+    }
+
+    // End of track
+    data.extend_from_slice(&[0x00, 0xff, 0x2f, 0x00]);
+
+    buff.extend_from_slice(b"MTrk");
+    buff.write_u32::<BigEndian>(data.len() as u32).unwrap();
+    buff.extend_from_slice(&data);
+}
+
+fn take(pending: &mut Pending, key: (u8, u8)) -> usize {
+    match pending.get_mut(&key) {
+        Some(ref mut tracks) if !tracks.is_empty() => tracks.remove(0),
+        _ => 0,
+    }
+}
+
+fn push(tracks: &mut Vec<Track>, track: usize, ticks: u64, evt: Vec<u8>) {
+    if tracks.len() <= track {
+        tracks.resize(track + 1, vec![]);
+    }
+    tracks[track].push((ticks, evt));
+}
+
+/// Write timed commands to a type 1 standard midi file
+///
+/// The first track holds tempo changes, followed by a track for each jez
+/// track. Midi messages are attributed to tracks through the events that
+/// precede them.
+pub fn write(tempo: f64, commands: &[(f64, Command)]) -> Vec<u8> {
+    let mut tempo_map = TempoMap::new(tempo);
+    let mut conductor = vec![(0, tempo_event(tempo))];
+    let mut tracks = vec![];
+
+    let mut scheduled = Pending::new();
+    let mut sounding = Pending::new();
+    let mut controls = HashMap::new();
+
+    for &(time, cmd) in commands {
+        match cmd {
+            Command::Tempo(tempo) => {
+                tempo_map.change(time, tempo);
+                conductor.push((tempo_map.ticks(time), tempo_event(tempo)));
+            }
+            Command::Event(event) => {
+                let Destination::Midi(chan, arg) = event.dest;
+                match event.value {
+                    EventValue::Trigger(pitch) => {
+                        let key = (chan, pitch as u8);
+                        scheduled.entry(key).or_default().push(event.track);
+                    }
+                    EventValue::Curve(_) => {
+                        controls.insert((chan, arg), event.track);
+                    }
+                }
+            }
+            Command::MidiNoteOn(chan, pitch, vel) => {
+                let track = take(&mut scheduled, (chan, pitch));
+                sounding.entry((chan, pitch)).or_default().push(track);
+                let evt = vec![144 + chan, pitch, vel];
+                push(&mut tracks, track, tempo_map.ticks(time), evt);
+            }
+            Command::MidiNoteOff(chan, pitch) => {
+                let track = take(&mut sounding, (chan, pitch));
+                let evt = vec![128 + chan, pitch, 0];
+                push(&mut tracks, track, tempo_map.ticks(time), evt);
+            }
+            Command::MidiCtl(chan, ctl, val) => {
+                let track = controls.get(&(chan, ctl)).cloned().unwrap_or(0);
+                let evt = vec![176 + chan, ctl, val];
+                push(&mut tracks, track, tempo_map.ticks(time), evt);
+            }
+            _ => (),
+        }
+    }
+
+    let mut buff = vec![];
+    buff.extend_from_slice(b"MThd");
+    buff.write_u32::<BigEndian>(6).unwrap();
+    buff.write_u16::<BigEndian>(1).unwrap();
+    buff.write_u16::<BigEndian>(tracks.len() as u16 + 1)
+        .unwrap();
+    buff.write_u16::<BigEndian>(PPQ).unwrap();
+
+    write_track(&mut buff, &mut conductor);
+    for track in &mut tracks {
+        write_track(&mut buff, track);
+    }
+
+    buff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Event;
+
+    fn note(pitch: f64, num: usize) -> Command {
+        Command::Event(Event {
+            dest: Destination::Midi(0, 127),
+            onset: 0.0,
+            dur: 250.0,
+            value: EventValue::Trigger(pitch),
+            track: num,
+        })
+    }
+
+    #[test]
+    fn test_vlq() {
+        let cases: &[(u64, &[u8])] = &[
+            (0, &[0x00]),
+            (0x40, &[0x40]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x81, 0x00]),
+            (0x2000, &[0xc0, 0x00]),
+            (0x0fff_ffff, &[0xff, 0xff, 0xff, 0x7f]),
+        ];
+        for &(val, expected) in cases {
+            let mut buff = vec![];
+            write_vlq(&mut buff, val);
+            assert_eq!(buff, expected);
+        }
+    }
+
+    #[test]
+    fn test_tempo_map() {
+        let mut tempo_map = TempoMap::new(120.0);
+        assert_eq!(tempo_map.ticks(500.0), 480);
+        tempo_map.change(1000.0, 60.0);
+        assert_eq!(tempo_map.ticks(1000.0), 960);
+        assert_eq!(tempo_map.ticks(2000.0), 1440);
+    }
+
+    #[test]
+    fn test_write_tracks() {
+        let commands = vec![
+            (0.0, note(60.0, 1)),
+            (0.0, note(64.0, 0)),
+            (0.0, Command::MidiNoteOn(0, 60, 127)),
+            (0.0, Command::MidiNoteOn(0, 64, 127)),
+            (250.0, Command::MidiNoteOff(0, 60)),
+            (250.0, Command::MidiNoteOff(0, 64)),
+        ];
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            // Header
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 3, 0x01, 0xe0,
+            // Tempo
+            b'M', b'T', b'r', b'k', 0, 0, 0, 11,
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+            0x00, 0xff, 0x2f, 0x00,
+            // Track 0
+            b'M', b'T', b'r', b'k', 0, 0, 0, 13,
+            0x00, 0x90, 64, 127,
+            0x81, 0x70, 0x80, 64, 0,
+            0x00, 0xff, 0x2f, 0x00,
+            // Track 1
+            b'M', b'T', b'r', b'k', 0, 0, 0, 13,
+            0x00, 0x90, 60, 127,
+            0x81, 0x70, 0x80, 60, 0,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+
+        assert_eq!(write(120.0, &commands), expected);
+    }
+}
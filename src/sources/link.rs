@@ -0,0 +1,456 @@
+use std::io::Cursor;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::err::Error;
+use crate::vm::{dur_to_millis, millis_to_dur, Command, TimeSource};
+
+const MAGIC: &[u8; 4] = b"JEZS";
+const VERSION: u8 = 1;
+const MESSAGE_SIZE: usize = 46;
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 74, 90);
+// Each peer listens on its own port from this range, so that several
+// instances can share a single host
+const BASE_PORT: u16 = 20909;
+const MAX_PEERS: u16 = 8;
+
+// Beats past the start of a quantum that still count as being on time
+const LATENESS: f64 = 0.1;
+
+const HEARTBEAT: u64 = 100;
+const JOIN_WAIT: u64 = 300;
+
+/// Maps local time, in milliseconds, to beats
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeline {
+    pub tempo: f64,
+    pub beat: f64,
+    pub time: f64,
+}
+
+impl Timeline {
+    pub fn beat_at(&self, time: f64) -> f64 {
+        self.beat + (time - self.time) * self.tempo / 60000.0
+    }
+
+    /// Change tempo at time `at`, without changing the beat at that time
+    pub fn with_tempo(&self, at: f64, bpm: f64) -> Timeline {
+        Timeline {
+            tempo: bpm,
+            beat: self.beat_at(at),
+            time: at,
+        }
+    }
+}
+
+/// The state of a session as broadcast by a peer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Message {
+    pub peer: u64,
+    pub owner: u64,
+    pub revision: u64,
+    pub tempo: f64,
+    pub beat: f64,
+    pub playing: bool,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buff = Vec::with_capacity(MESSAGE_SIZE);
+        buff.extend_from_slice(MAGIC);
+        buff.push(VERSION);
+        buff.write_u64::<BigEndian>(self.peer).unwrap();
+        buff.write_u64::<BigEndian>(self.owner).unwrap();
+        buff.write_u64::<BigEndian>(self.revision).unwrap();
+        buff.write_f64::<BigEndian>(self.tempo).unwrap();
+        buff.write_f64::<BigEndian>(self.beat).unwrap();
+        buff.push(self.playing as u8);
+        buff
+    }
+
+    pub fn decode(buff: &[u8]) -> Option<Message> {
+        if buff.len() != MESSAGE_SIZE || &buff[0..4] != MAGIC || buff[4] != VERSION {
+            return None;
+        }
+
+        let mut rdr = Cursor::new(&buff[5..]);
+        Some(Message {
+            peer: rdr.read_u64::<BigEndian>().ok()?,
+            owner: rdr.read_u64::<BigEndian>().ok()?,
+            revision: rdr.read_u64::<BigEndian>().ok()?,
+            tempo: rdr.read_f64::<BigEndian>().ok()?,
+            beat: rdr.read_f64::<BigEndian>().ok()?,
+            playing: rdr.read_u8().ok()? != 0,
+        })
+    }
+}
+
+/// A peers view of the shared session
+///
+/// Every change to tempo or transport bumps the revision, the most recent
+/// change (ties broken by peer id) is adopted by all peers. The peer that
+/// made it is the owner of the session, and its beat is followed by others.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    peer: u64,
+    owner: u64,
+    revision: u64,
+    playing: bool,
+    timeline: Timeline,
+}
+
+impl Session {
+    pub fn new(id: u64, now: f64, bpm: f64) -> Session {
+        Session {
+            peer: id,
+            owner: id,
+            revision: 0,
+            playing: false,
+            timeline: Timeline {
+                tempo: bpm,
+                beat: 0.0,
+                time: now,
+            },
+        }
+    }
+
+    pub fn message(&self, time: f64) -> Message {
+        Message {
+            peer: self.peer,
+            owner: self.owner,
+            revision: self.revision,
+            tempo: self.timeline.tempo,
+            beat: self.timeline.beat_at(time),
+            playing: self.playing,
+        }
+    }
+
+    pub fn receive(&mut self, now: f64, msg: &Message) {
+        if msg.peer == self.peer {
+            return;
+        }
+
+        let newer = (msg.revision, msg.owner) > (self.revision, self.owner);
+        let current = (msg.revision, msg.owner) == (self.revision, self.owner);
+        if newer || (current && msg.peer == self.owner) {
+            self.owner = msg.owner;
+            self.revision = msg.revision;
+            self.playing = msg.playing;
+            self.timeline = Timeline {
+                tempo: msg.tempo,
+                beat: msg.beat,
+                time: now,
+            };
+        }
+    }
+
+    pub fn set_tempo(&mut self, time: f64, tempo: f64) -> bool {
+        if tempo <= 0.0 || (tempo - self.timeline.tempo).abs() < f64::EPSILON {
+            return false;
+        }
+        self.timeline = self.timeline.with_tempo(time, tempo);
+        self.change();
+        true
+    }
+
+    pub fn set_playing(&mut self, now: f64, playing: bool) -> bool {
+        if playing == self.playing {
+            return false;
+        }
+        if playing {
+            // Starting resets the beat, so the starting peer is not left
+            // waiting for the next quantum
+            self.timeline = Timeline {
+                tempo: self.timeline.tempo,
+                beat: 0.0,
+                time: now,
+            };
+        }
+        self.playing = playing;
+        self.change();
+        true
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn timeline(&self) -> Timeline {
+        self.timeline
+    }
+
+    fn change(&mut self) {
+        self.revision += 1;
+        self.owner = self.peer;
+    }
+}
+
+/// Shares tempo, beat and transport with other peers on the network
+#[derive(Clone, Debug)]
+pub struct LinkSession {
+    session: Arc<Mutex<Session>>,
+    socket: Arc<UdpSocket>,
+    epoch: Instant,
+}
+
+fn bind() -> Result<UdpSocket, Error> {
+    let mut res = Err(error!(UnreachableBackend, "No free ports for link"));
+    for port in BASE_PORT..BASE_PORT + MAX_PEERS {
+        if let Ok(socket) = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)) {
+            res = Ok(socket);
+            break;
+        }
+    }
+    res
+}
+
+impl LinkSession {
+    /// Join a session, starting one at `tempo` if no peers are playing
+    pub fn join(tempo: f64) -> Result<LinkSession, Error> {
+        let socket = bind()?;
+        socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+
+        let link = LinkSession {
+            session: Arc::new(Mutex::new(Session::new(rand::random(), 0.0, tempo))),
+            socket: Arc::new(socket),
+            epoch: Instant::now(),
+        };
+
+        let receiver = link.clone();
+        thread::spawn(move || {
+            let mut buff = [0; MESSAGE_SIZE];
+            while let Ok((len, _)) = receiver.socket.recv_from(&mut buff) {
+                if let Some(msg) = Message::decode(&buff[..len]) {
+                    let now = receiver.now();
+                    receiver.session.lock().unwrap().receive(now, &msg);
+                }
+            }
+        });
+
+        let heartbeat = link.clone();
+        thread::spawn(move || loop {
+            heartbeat.broadcast();
+            thread::sleep(Duration::from_millis(HEARTBEAT));
+        });
+
+        // Give peers a chance to be heard before deciding to start
+        thread::sleep(Duration::from_millis(JOIN_WAIT));
+        if !link.session.lock().unwrap().playing() {
+            link.set_tempo(tempo);
+            link.start();
+        }
+        Ok(link)
+    }
+
+    pub fn set_tempo(&self, tempo: f64) {
+        let now = self.now();
+        if self.session.lock().unwrap().set_tempo(now, tempo) {
+            self.broadcast();
+        }
+    }
+
+    pub fn start(&self) {
+        let now = self.now();
+        if self.session.lock().unwrap().set_playing(now, true) {
+            self.broadcast();
+        }
+    }
+
+    pub fn stop(&self) {
+        let now = self.now();
+        if self.session.lock().unwrap().set_playing(now, false) {
+            self.broadcast();
+        }
+    }
+
+    /// Returns a time source for a machine that starts on the next multiple
+    /// of `beats`, tempo changes are sent as commands to `channel`
+    pub fn time_source(&self, channel: Sender<Command>, beats: f64) -> LinkTime {
+        LinkTime {
+            link: self.clone(),
+            host: channel,
+            quantum: beats,
+            beat: None,
+            tempo: None,
+        }
+    }
+
+    fn now(&self) -> f64 {
+        dur_to_millis(self.epoch.elapsed())
+    }
+
+    fn broadcast(&self) {
+        let now = self.now();
+        let buff = self.session.lock().unwrap().message(now).encode();
+        for port in BASE_PORT..BASE_PORT + MAX_PEERS {
+            self.socket.send_to(&buff, (GROUP, port)).ok();
+        }
+    }
+}
+
+/// Time that advances with the beat of a shared session
+#[derive(Debug)]
+pub struct LinkTime {
+    link: LinkSession,
+    host: Sender<Command>,
+    quantum: f64,
+    beat: Option<f64>,
+    tempo: Option<f64>,
+}
+
+impl LinkTime {
+    fn advance(&mut self, session: &Session, time: f64) -> f64 {
+        let timeline = session.timeline();
+        if self.tempo != Some(timeline.tempo) {
+            self.tempo = Some(timeline.tempo);
+            self.host.send(Command::Tempo(timeline.tempo)).unwrap_or(());
+        }
+
+        if !session.playing() {
+            self.beat = None;
+            return 0.0;
+        }
+
+        let beat = timeline.beat_at(time);
+        let previous = match self.beat {
+            Some(previous) => previous,
+            None => {
+                // Wait for the start of the next quantum, before starting or
+                // resuming
+                let start = ((beat - LATENESS) / self.quantum).ceil() * self.quantum;
+                self.beat = Some(start);
+                start
+            }
+        };
+
+        // Time stands still until the beat catches up, if the phase of the
+        // session moves backwards
+        if beat <= previous {
+            return 0.0;
+        }
+
+        self.beat = Some(beat);
+        (beat - previous) * 60000.0 / timeline.tempo
+    }
+}
+
+impl TimeSource for LinkTime {
+    fn delta(&mut self) -> Duration {
+        let now = self.link.now();
+        let session = self.link.session.lock().unwrap().clone();
+        millis_to_dur(self.advance(&session, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn message(from: u64, owned_by: u64, rev: u64) -> Message {
+        Message {
+            peer: from,
+            owner: owned_by,
+            revision: rev,
+            tempo: 90.0,
+            beat: 8.0,
+            playing: true,
+        }
+    }
+
+    #[test]
+    fn test_timeline() {
+        let timeline = Timeline {
+            tempo: 120.0,
+            beat: 4.0,
+            time: 1000.0,
+        };
+        assert_eq!(timeline.beat_at(1000.0), 4.0);
+        assert_eq!(timeline.beat_at(2000.0), 6.0);
+
+        let timeline = timeline.with_tempo(2000.0, 60.0);
+        assert_eq!(timeline.beat_at(2000.0), 6.0);
+        assert_eq!(timeline.beat_at(3000.0), 7.0);
+    }
+
+    #[test]
+    fn test_message_encoding() {
+        let msg = message(1, 2, 3);
+        let buff = msg.encode();
+        assert_eq!(buff.len(), MESSAGE_SIZE);
+        assert_eq!(Message::decode(&buff), Some(msg));
+        assert_eq!(Message::decode(&buff[1..]), None);
+
+        let mut buff = buff;
+        buff[4] = VERSION + 1;
+        assert_eq!(Message::decode(&buff), None);
+    }
+
+    #[test]
+    fn test_session_merge() {
+        let mut session = Session::new(1, 0.0, 120.0);
+        assert!(session.set_playing(0.0, true));
+        assert!(!session.set_playing(0.0, true));
+
+        // Older changes are ignored
+        session.receive(500.0, &message(2, 2, 0));
+        assert_eq!(session.timeline().tempo, 120.0);
+
+        // Newer changes are adopted, along with the beat of the sender
+        session.receive(500.0, &message(2, 2, 1));
+        assert_eq!(session.timeline().tempo, 90.0);
+        assert_eq!(session.timeline().beat_at(500.0), 8.0);
+
+        // Only the owner moves the beat of an unchanged session
+        session.receive(1000.0, &message(3, 2, 1));
+        assert_eq!(session.timeline().beat_at(1000.0), 8.75);
+        session.receive(1000.0, &message(2, 2, 1));
+        assert_eq!(session.timeline().beat_at(1000.0), 8.0);
+
+        // Local changes take ownership
+        assert!(session.set_tempo(1000.0, 100.0));
+        let msg = session.message(1000.0);
+        assert_eq!((msg.peer, msg.owner, msg.revision), (1, 1, 2));
+        assert_eq!(msg.beat, 8.0);
+    }
+
+    #[test]
+    fn test_quantised_start() {
+        let (send, recv) = channel();
+        let link = LinkSession {
+            session: Arc::new(Mutex::new(Session::new(1, 0.0, 120.0))),
+            socket: Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap()),
+            epoch: Instant::now(),
+        };
+        let mut time = link.time_source(send.clone(), 4.0);
+
+        let mut session = Session::new(1, 0.0, 120.0);
+        assert_eq!(time.advance(&session, 500.0), 0.0);
+        assert_eq!(recv.try_recv(), Ok(Command::Tempo(120.0)));
+
+        // Starting a session starts immediately
+        session.set_playing(1000.0, true);
+        assert_eq!(time.advance(&session, 1010.0), 10.0);
+        assert_eq!(time.advance(&session, 1500.0), 490.0);
+
+        // Joining a session waits for the next quantum
+        let mut other = link.time_source(send, 4.0);
+        assert_eq!(other.advance(&session, 2000.0), 0.0);
+        assert_eq!(other.advance(&session, 3000.0), 0.0);
+        assert_eq!(other.advance(&session, 3500.0), 500.0);
+        assert_eq!(recv.try_recv(), Ok(Command::Tempo(120.0)));
+
+        // Tempo changes are passed on to the machine
+        session.set_tempo(1500.0, 60.0);
+        assert_eq!(time.advance(&session, 2500.0), 1000.0);
+        assert_eq!(recv.try_recv(), Ok(Command::Tempo(60.0)));
+        assert!(recv.try_recv().is_err());
+    }
+}
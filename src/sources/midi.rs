@@ -0,0 +1,342 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::err::Error;
+use crate::vm::{dur_to_millis, millis_to_dur, Command, TimeSource, PULSES_PER_BEAT};
+
+// Weight given to each new pulse interval when smoothing the tempo estimate
+const SMOOTHING: f64 = 0.1;
+// Smallest change in the estimated tempo that is passed on to the machine
+const TEMPO_THRESHOLD: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Realtime {
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+impl Realtime {
+    pub fn parse(status: u8) -> Option<Realtime> {
+        match status {
+            248 => Some(Realtime::Clock),
+            250 => Some(Realtime::Start),
+            251 => Some(Realtime::Continue),
+            252 => Some(Realtime::Stop),
+            _ => None,
+        }
+    }
+}
+
+/// Estimates tempo from the interval between clock pulses
+#[derive(Clone, Debug)]
+pub struct TempoEstimator {
+    previous: Option<f64>,
+    interval: Option<f64>,
+}
+
+impl TempoEstimator {
+    pub fn new() -> TempoEstimator {
+        TempoEstimator {
+            previous: None,
+            interval: None,
+        }
+    }
+
+    pub fn pulse(&mut self, time: f64) {
+        if let Some(previous) = self.previous {
+            let interval = time - previous;
+            self.interval = match self.interval {
+                // A long gap is a pause in the clock rather than a change in
+                // tempo, so the current estimate is kept
+                Some(current) if interval > current * 4.0 => Some(current),
+                Some(current) => Some(current + (interval - current) * SMOOTHING),
+                None if interval > 0.0 => Some(interval),
+                None => None,
+            };
+        }
+        self.previous = Some(time);
+    }
+
+    pub fn tempo(&self) -> Option<f64> {
+        match self.interval {
+            Some(interval) if interval > 0.0 => Some(60000.0 / (interval * PULSES_PER_BEAT)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Transport {
+    playing: bool,
+    pulses: u64,
+    // Number of times the transport has been started from the top
+    starts: u64,
+    estimator: TempoEstimator,
+}
+
+impl Transport {
+    fn receive(&mut self, time: f64, msg: Realtime) {
+        match msg {
+            Realtime::Clock => {
+                self.estimator.pulse(time);
+                if self.playing {
+                    self.pulses += 1;
+                }
+            }
+            Realtime::Start => {
+                self.pulses = 0;
+                self.starts += 1;
+                self.playing = true;
+            }
+            Realtime::Continue => self.playing = true,
+            Realtime::Stop => self.playing = false,
+        }
+    }
+}
+
+/// Follows the clock and transport messages of an external MIDI device
+#[derive(Clone, Debug)]
+pub struct MidiFollower {
+    transport: Arc<Mutex<Transport>>,
+}
+
+impl MidiFollower {
+    fn new() -> MidiFollower {
+        MidiFollower {
+            transport: Arc::new(Mutex::new(Transport {
+                playing: false,
+                pulses: 0,
+                starts: 0,
+                estimator: TempoEstimator::new(),
+            })),
+        }
+    }
+
+    /// Follow messages from a MIDI input device
+    pub fn listen(device: Option<usize>) -> Result<MidiFollower, Error> {
+        let follower = MidiFollower::new();
+        self::input(device, follower.clone())?;
+        Ok(follower)
+    }
+
+    /// Receive a MIDI status byte, with its time in milliseconds
+    pub fn receive(&self, time: f64, status: u8) {
+        if let Some(msg) = Realtime::parse(status) {
+            self.transport.lock().unwrap().receive(time, msg);
+        }
+    }
+
+    /// Returns a time source for a machine, tempo changes are sent as
+    /// commands to `channel`
+    pub fn time_source(&self, channel: Sender<Command>) -> MidiTime {
+        let transport = self.transport.lock().unwrap();
+        MidiTime {
+            transport: self.transport.clone(),
+            host: channel,
+            previous: Instant::now(),
+            pulses: transport.pulses,
+            starts: transport.starts,
+            anchor: 0.0,
+            position: 0.0,
+            tempo: None,
+        }
+    }
+}
+
+#[cfg(feature = "with-portmidi")]
+use super::portmidi::listen as input;
+
+#[cfg(not(feature = "with-portmidi"))]
+fn input(_: Option<usize>, _: MidiFollower) -> Result<(), Error> {
+    Err(error!(UnknownBackend, "portmidi"))
+}
+
+/// Time that advances with the pulses of an external MIDI clock
+///
+/// Between pulses time follows the system clock, but never runs ahead of
+/// where the next pulse is expected. Time stands still while the transport
+/// is stopped, a start counts pulses from the top while continue resumes
+/// from where the transport stopped.
+#[derive(Debug)]
+pub struct MidiTime {
+    transport: Arc<Mutex<Transport>>,
+    host: Sender<Command>,
+    previous: Instant,
+    pulses: u64,
+    starts: u64,
+    anchor: f64,
+    position: f64,
+    tempo: Option<f64>,
+}
+
+impl MidiTime {
+    fn advance(&mut self, elapsed: f64) -> f64 {
+        let (playing, pulses, starts, estimate) = {
+            let transport = self.transport.lock().unwrap();
+            (
+                transport.playing,
+                transport.pulses,
+                transport.starts,
+                transport.estimator.tempo(),
+            )
+        };
+
+        if starts != self.starts {
+            self.starts = starts;
+            self.pulses = 0;
+            self.anchor = 0.0;
+            self.position = 0.0;
+        }
+
+        if let Some(estimate) = estimate {
+            let changed = match self.tempo {
+                Some(tempo) => (estimate - tempo).abs() >= TEMPO_THRESHOLD,
+                None => true,
+            };
+            if changed {
+                self.tempo = Some(estimate);
+                self.host.send(Command::Tempo(estimate)).unwrap_or(());
+            }
+        }
+
+        // Pulses are measured with the tempo known to the machine, keeping
+        // beats in step with the clock
+        let interval = match self.tempo {
+            Some(tempo) => 60000.0 / (tempo * PULSES_PER_BEAT),
+            None => return 0.0,
+        };
+
+        let start = self.position;
+        if pulses > self.pulses {
+            self.anchor += (pulses - self.pulses) as f64 * interval;
+            self.pulses = pulses;
+            self.position = self.position.max(self.anchor);
+        }
+
+        if playing {
+            self.position = (self.position + elapsed).min(self.anchor + interval);
+        }
+
+        self.position - start
+    }
+}
+
+impl TimeSource for MidiTime {
+    fn delta(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = dur_to_millis(now.duration_since(self.previous));
+        self.previous = now;
+        millis_to_dur(self.advance(elapsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    // Interval between pulses at 120 bpm
+    const PULSE: f64 = 500.0 / 24.0;
+
+    #[test]
+    fn test_parse_realtime() {
+        assert_eq!(Realtime::parse(248), Some(Realtime::Clock));
+        assert_eq!(Realtime::parse(250), Some(Realtime::Start));
+        assert_eq!(Realtime::parse(251), Some(Realtime::Continue));
+        assert_eq!(Realtime::parse(252), Some(Realtime::Stop));
+        assert_eq!(Realtime::parse(144), None);
+    }
+
+    #[test]
+    fn test_tempo_estimator() {
+        let mut estimator = TempoEstimator::new();
+        assert_eq!(estimator.tempo(), None);
+        estimator.pulse(0.0);
+        assert_eq!(estimator.tempo(), None);
+        estimator.pulse(PULSE);
+        assert!(approx(estimator.tempo().unwrap(), 120.0));
+
+        // Jitter is smoothed out
+        estimator.pulse(PULSE * 2.0 + 2.0);
+        let tempo = estimator.tempo().unwrap();
+        assert!(tempo < 120.0 && tempo > 118.0);
+
+        // Pauses are ignored
+        estimator.pulse(10000.0);
+        assert!(approx(estimator.tempo().unwrap(), tempo));
+    }
+
+    #[test]
+    fn test_follow_pulses() {
+        let (send, recv) = channel();
+        let follower = MidiFollower::new();
+        let mut time = follower.time_source(send);
+
+        follower.receive(0.0, 248);
+        follower.receive(PULSE, 248);
+        assert_eq!(time.advance(PULSE), 0.0);
+        match recv.try_recv() {
+            Ok(Command::Tempo(tempo)) => assert!(approx(tempo, 120.0)),
+            res => panic!("Unexpected {:?}", res),
+        }
+
+        // Time advances only up to the next expected pulse
+        follower.receive(PULSE, 250);
+        assert!(approx(time.advance(PULSE / 2.0), PULSE / 2.0));
+        assert!(approx(time.advance(PULSE), PULSE / 2.0));
+        assert!(approx(time.advance(PULSE), 0.0));
+
+        follower.receive(PULSE * 2.0, 248);
+        assert!(approx(time.advance(0.0), 0.0));
+        assert!(approx(time.advance(PULSE), PULSE));
+
+        // Late pulses catch time up to the clock
+        follower.receive(PULSE * 3.0, 248);
+        follower.receive(PULSE * 4.0, 248);
+        assert!(approx(time.advance(0.0), PULSE));
+
+        follower.receive(PULSE * 4.0, 252);
+        assert_eq!(time.advance(PULSE), 0.0);
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_start_and_continue() {
+        let (send, _recv) = channel();
+        let follower = MidiFollower::new();
+        let mut time = follower.time_source(send);
+
+        follower.receive(0.0, 248);
+        follower.receive(PULSE, 248);
+        follower.receive(PULSE, 250);
+        for pulse in 2..5 {
+            follower.receive(PULSE * pulse as f64, 248);
+        }
+        assert!(approx(time.advance(0.0), PULSE * 3.0));
+        assert_eq!(follower.transport.lock().unwrap().pulses, 3);
+
+        // Continue resumes counting from where the transport stopped
+        follower.receive(PULSE * 4.0, 252);
+        follower.receive(PULSE * 5.0, 248);
+        follower.receive(PULSE * 5.0, 251);
+        follower.receive(PULSE * 6.0, 248);
+        assert!(approx(time.advance(0.0), PULSE));
+        assert_eq!(follower.transport.lock().unwrap().pulses, 4);
+
+        // Start goes back to the top
+        follower.receive(PULSE * 6.0, 252);
+        follower.receive(PULSE * 7.0, 248);
+        follower.receive(PULSE * 7.0, 250);
+        assert_eq!(follower.transport.lock().unwrap().pulses, 0);
+        follower.receive(PULSE * 8.0, 248);
+        assert!(approx(time.advance(0.0), PULSE));
+        assert_eq!(time.position, PULSE);
+    }
+
+    fn approx(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+}
@@ -0,0 +1,9 @@
+mod link;
+mod midi;
+mod osc;
+#[cfg(feature = "with-portmidi")]
+mod portmidi;
+
+pub use self::link::{LinkSession, LinkTime};
+pub use self::midi::{MidiFollower, MidiTime};
+pub use self::osc::OscReceiver;
@@ -0,0 +1,167 @@
+use std::net::UdpSocket;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use rosc::decoder::{self, MTU};
+use rosc::{OscMessage, OscPacket, OscType};
+
+use crate::err::Error;
+use crate::lang::hash_str;
+use crate::vm::Command;
+
+/// Receives OSC messages that control a running program
+///
+/// Messages understood are:
+///
+///   /jez/global name value  Set a global variable
+///   /jez/reload             Reload the program
+///   /jez/stop               Stop the program
+///   /jez/mute name          Mute a track
+///   /jez/unmute name        Unmute a track
+///   /jez/solo name          Solo a track
+///   /jez/unsolo name        Unsolo a track
+///   /jez/stop name          Stop a track
+///   /jez/start name         Restart a stopped track
+#[derive(Debug)]
+pub struct OscReceiver {
+    sock: UdpSocket,
+}
+
+impl OscReceiver {
+    pub fn new(addr: &str) -> Result<OscReceiver, Error> {
+        Ok(OscReceiver {
+            sock: UdpSocket::bind(addr)?,
+        })
+    }
+
+    /// Send commands decoded from incoming packets to `channel`
+    pub fn run_forever(self, channel: Sender<Command>) {
+        thread::spawn(move || {
+            let mut buff = [0; MTU];
+            loop {
+                let size = match self.sock.recv_from(&mut buff) {
+                    Ok((size, _)) => size,
+                    Err(_) => return,
+                };
+                if let Ok(packet) = decoder::decode(&buff[..size]) {
+                    for cmd in decode(&packet) {
+                        if channel.send(cmd).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn number(arg: &OscType) -> Option<f64> {
+    match *arg {
+        OscType::Int(val) => Some(f64::from(val)),
+        OscType::Long(val) => Some(val as f64),
+        OscType::Float(val) => Some(f64::from(val)),
+        OscType::Double(val) => Some(val),
+        _ => None,
+    }
+}
+
+fn name(arg: &OscType) -> Option<u64> {
+    match *arg {
+        OscType::String(ref val) => Some(hash_str(val)),
+        _ => None,
+    }
+}
+
+fn decode_message(msg: &OscMessage) -> Option<Command> {
+    match (msg.addr.as_str(), msg.args.as_slice()) {
+        ("/jez/global", [var, val]) => Some(Command::SetGlobal(name(var)?, number(val)?)),
+        ("/jez/reload", []) => Some(Command::Reload),
+        ("/jez/stop", []) => Some(Command::Stop),
+        ("/jez/mute", [track]) => Some(Command::Mute(name(track)?)),
+        ("/jez/unmute", [track]) => Some(Command::Unmute(name(track)?)),
+        ("/jez/solo", [track]) => Some(Command::Solo(name(track)?)),
+        ("/jez/unsolo", [track]) => Some(Command::Unsolo(name(track)?)),
+        ("/jez/stop", [track]) => Some(Command::StopTrack(name(track)?)),
+        ("/jez/start", [track]) => Some(Command::StartTrack(name(track)?)),
+        _ => None,
+    }
+}
+
+/// Decode the commands in a packet, messages that are not understood are
+/// ignored
+pub fn decode(packet: &OscPacket) -> Vec<Command> {
+    match *packet {
+        OscPacket::Message(ref msg) => decode_message(msg).into_iter().collect(),
+        OscPacket::Bundle(ref bundle) => bundle.content.iter().flat_map(decode).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscBundle;
+
+    fn message(addr: &str, vals: Vec<OscType>) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args: vals,
+        })
+    }
+
+    #[test]
+    fn test_decode_messages() {
+        let var = OscType::String("x".to_string());
+        let track = OscType::String("t1".to_string());
+        let cases = vec![
+            (
+                message("/jez/global", vec![var.clone(), OscType::Float(0.5)]),
+                vec![Command::SetGlobal(hash_str("x"), 0.5)],
+            ),
+            (
+                message("/jez/global", vec![var.clone(), OscType::Int(3)]),
+                vec![Command::SetGlobal(hash_str("x"), 3.0)],
+            ),
+            (message("/jez/reload", vec![]), vec![Command::Reload]),
+            (message("/jez/stop", vec![]), vec![Command::Stop]),
+            (
+                message("/jez/mute", vec![track.clone()]),
+                vec![Command::Mute(hash_str("t1"))],
+            ),
+            (
+                message("/jez/unmute", vec![track.clone()]),
+                vec![Command::Unmute(hash_str("t1"))],
+            ),
+            (
+                message("/jez/solo", vec![track.clone()]),
+                vec![Command::Solo(hash_str("t1"))],
+            ),
+            (
+                message("/jez/unsolo", vec![track.clone()]),
+                vec![Command::Unsolo(hash_str("t1"))],
+            ),
+            (
+                message("/jez/stop", vec![track.clone()]),
+                vec![Command::StopTrack(hash_str("t1"))],
+            ),
+            (
+                message("/jez/start", vec![track]),
+                vec![Command::StartTrack(hash_str("t1"))],
+            ),
+            (message("/jez/global", vec![var]), vec![]),
+            (message("/jez/unknown", vec![]), vec![]),
+        ];
+
+        for (packet, expected) in cases {
+            assert_eq!(decode(&packet), expected);
+        }
+    }
+
+    #[test]
+    fn test_decode_bundle() {
+        let packet = OscPacket::Bundle(OscBundle {
+            timetag: OscType::Time(0, 1),
+            content: vec![message("/jez/stop", vec![]), message("/jez/reload", vec![])],
+        });
+        assert_eq!(decode(&packet), vec![Command::Stop, Command::Reload]);
+    }
+}
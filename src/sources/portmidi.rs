@@ -0,0 +1,57 @@
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use portmidi as pm;
+
+use crate::err::Error;
+
+use super::midi::MidiFollower;
+
+const BUFFER_SIZE: usize = 1024;
+
+fn open(ctx: &pm::PortMidi, id: Option<usize>) -> Result<pm::InputPort, Error> {
+    let id = match id {
+        Some(id) => id as i32,
+        None => ctx.default_input_device_id()?,
+    };
+    let info = ctx.device(id)?;
+    Ok(ctx.input_port(info, BUFFER_SIZE)?)
+}
+
+/// Read messages from a MIDI input device on a background thread
+pub fn listen(id: Option<usize>, follower: MidiFollower) -> Result<(), Error> {
+    let (status_send, status_recv) = channel();
+
+    thread::spawn(move || {
+        let res = pm::PortMidi::new()
+            .map_err(Error::from)
+            .and_then(|ctx| open(&ctx, id).map(|port| (ctx, port)));
+
+        let (_ctx, port) = match res {
+            Ok(res) => {
+                status_send.send(Ok(())).unwrap_or(());
+                res
+            }
+            Err(err) => {
+                status_send.send(Err(err)).unwrap_or(());
+                return;
+            }
+        };
+
+        let res = Duration::new(0, 1_000_000); // 1ms
+        loop {
+            if let Ok(Some(events)) = port.read_n(BUFFER_SIZE) {
+                for event in events {
+                    follower.receive(f64::from(event.timestamp), event.message.status);
+                }
+            }
+            thread::sleep(res);
+        }
+    });
+
+    match status_recv.recv() {
+        Ok(status) => status,
+        Err(_) => Err(error!(UnreachableBackend)),
+    }
+}
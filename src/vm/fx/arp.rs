@@ -0,0 +1,245 @@
+use rand::{Rng, StdRng};
+
+use crate::lang::hash_str;
+use crate::vm::types::{Effect, Event, EventValue};
+
+/// Order notes of a chord are played in
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+#[derive(Clone)]
+pub struct Arpeggiator {
+    mode: Mode,
+    rate: usize,
+    octaves: usize,
+    gate: f64,
+    rng: StdRng,
+}
+
+impl Arpeggiator {
+    /// An arpeggiator dividing chords into `rate` steps, over a number of
+    /// octaves, with notes held for a fraction `gate` of each step
+    pub fn new(
+        mode: u64,
+        rate: usize,
+        octaves: usize,
+        gate: f64,
+        rng: StdRng,
+    ) -> Option<Arpeggiator> {
+        let mode = match mode {
+            m if m == hash_str("up") => Mode::Up,
+            m if m == hash_str("down") => Mode::Down,
+            m if m == hash_str("up_down") => Mode::UpDown,
+            m if m == hash_str("random") => Mode::Random,
+            m if m == hash_str("as_played") => Mode::AsPlayed,
+            _ => return None,
+        };
+
+        // NaN fails the comparison, and would give every note a NaN length
+        if rate == 0 || octaves == 0 || !(gate > 0.0 && gate <= 1.0) {
+            return None;
+        }
+
+        Some(Arpeggiator {
+            mode,
+            rate,
+            octaves,
+            gate,
+            rng,
+        })
+    }
+
+    /// Notes of a chord in the order they are stepped through
+    fn pattern(&self, chord: &[Event]) -> Vec<Event> {
+        let mut notes = chord.to_vec();
+        if self.mode != Mode::AsPlayed {
+            notes.sort_by(|a, b| pitch(a).total_cmp(&pitch(b)));
+        }
+
+        let mut pattern = vec![];
+        for octave in 0..self.octaves {
+            for note in &notes {
+                let mut note = *note;
+                note.value = EventValue::Trigger(pitch(&note) + (octave * 12) as f64);
+                pattern.push(note);
+            }
+        }
+
+        match self.mode {
+            Mode::Down => pattern.reverse(),
+            Mode::UpDown if pattern.len() > 2 => {
+                // The highest and lowest notes aren't repeated on the turn
+                let down: Vec<Event> = pattern[1..pattern.len() - 1]
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect();
+                pattern.extend(down);
+            }
+            _ => (),
+        }
+        pattern
+    }
+
+    fn arpeggiate(&mut self, chord: &[Event], output: &mut Vec<Event>) {
+        let pattern = self.pattern(chord);
+        let onset = chord[0].onset;
+        let dur = chord.iter().map(|note| note.dur).fold(0.0, f64::max);
+        let step = dur / self.rate as f64;
+
+        for i in 0..self.rate {
+            let idx = match self.mode {
+                Mode::Random => self.rng.gen_range(0, pattern.len()),
+                _ => i % pattern.len(),
+            };
+            let mut note = pattern[idx];
+            note.onset = onset + i as f64 * step;
+            note.dur = step * self.gate;
+            output.push(note);
+        }
+    }
+}
+
+fn pitch(event: &Event) -> f64 {
+    match event.value {
+        EventValue::Trigger(pitch) => pitch,
+        EventValue::Curve(_) => 0.0,
+    }
+}
+
+impl Effect for Arpeggiator {
+    fn apply(&mut self, _: f64, events: &[Event]) -> Vec<Event> {
+        let mut output = Vec::with_capacity(events.len() * self.rate);
+        let mut chords: Vec<Vec<Event>> = vec![];
+
+        for event in events {
+            match event.value {
+                // Curves aren't notes, and are left as they are
+                EventValue::Curve(_) => output.push(*event),
+                EventValue::Trigger(_) => {
+                    match chords
+                        .iter_mut()
+                        .find(|chord| chord[0].onset == event.onset)
+                    {
+                        Some(chord) => chord.push(*event),
+                        None => chords.push(vec![*event]),
+                    }
+                }
+            }
+        }
+
+        for mut chord in chords {
+            // Values of a group are output last first
+            chord.reverse();
+            self.arpeggiate(&chord, &mut output);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::types::Destination;
+
+    use rand::SeedableRng;
+
+    fn event(onset: f64, dur: f64, val: f64) -> Event {
+        Event {
+            dest: Destination::Midi(0, 127),
+            onset,
+            dur,
+            value: EventValue::Trigger(val),
+            track: 0,
+        }
+    }
+
+    fn arp(mode: &str, rate: usize, octaves: usize) -> Arpeggiator {
+        let seed: &[_] = &[8, 8, 8, 8];
+        Arpeggiator::new(
+            hash_str(mode),
+            rate,
+            octaves,
+            0.5,
+            SeedableRng::from_seed(seed),
+        )
+        .unwrap()
+    }
+
+    /// A chord as output by `midi_out` for `{64 60 67}`
+    fn chord() -> Vec<Event> {
+        vec![
+            event(0.0, 800.0, 67.0),
+            event(0.0, 800.0, 60.0),
+            event(0.0, 800.0, 64.0),
+        ]
+    }
+
+    fn pitches(events: &[Event]) -> Vec<f64> {
+        events.iter().map(pitch).collect()
+    }
+
+    #[test]
+    fn test_modes() {
+        let cases = [
+            ("up", vec![60.0, 64.0, 67.0, 60.0]),
+            ("down", vec![67.0, 64.0, 60.0, 67.0]),
+            ("up_down", vec![60.0, 64.0, 67.0, 64.0]),
+            ("as_played", vec![64.0, 60.0, 67.0, 64.0]),
+        ];
+        for (mode, expected) in cases.iter() {
+            let output = arp(mode, 4, 1).apply(800.0, &chord());
+            assert_eq!(&pitches(&output), expected, "{}", mode);
+        }
+
+        let output = arp("random", 8, 1).apply(800.0, &chord());
+        assert_eq!(output.len(), 8);
+        assert!(pitches(&output)
+            .iter()
+            .all(|p| [60.0, 64.0, 67.0].contains(p)));
+    }
+
+    #[test]
+    fn test_octaves_and_gate() {
+        let output = arp("up", 6, 2).apply(800.0, &chord());
+        assert_eq!(pitches(&output), vec![60.0, 64.0, 67.0, 72.0, 76.0, 79.0]);
+        let onsets: Vec<f64> = output.iter().map(|note| note.onset).collect();
+        let step = 800.0 / 6.0;
+        assert_eq!(onsets[1], step);
+        assert_eq!(onsets[5], 5.0 * step);
+        assert!(output.iter().all(|note| note.dur == step * 0.5));
+    }
+
+    #[test]
+    fn test_chords_apart() {
+        let mut events = chord();
+        events.push(event(800.0, 800.0, 48.0));
+        let output = arp("up", 2, 1).apply(1600.0, &events);
+        assert_eq!(pitches(&output), vec![60.0, 64.0, 48.0, 48.0]);
+        assert_eq!(output[2].onset, 800.0);
+    }
+
+    #[test]
+    fn test_nan_pitch() {
+        let mut events = chord();
+        events.push(event(0.0, 800.0, f64::NAN));
+        let output = arp("up", 4, 1).apply(800.0, &events);
+        assert_eq!(output.len(), 4);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let seed: &[_] = &[8, 8, 8, 8];
+        let rng: StdRng = SeedableRng::from_seed(seed);
+        assert!(Arpeggiator::new(hash_str("sideways"), 4, 1, 0.5, rng).is_none());
+        assert!(Arpeggiator::new(hash_str("up"), 0, 1, 0.5, rng).is_none());
+        assert!(Arpeggiator::new(hash_str("up"), 4, 1, 1.5, rng).is_none());
+        assert!(Arpeggiator::new(hash_str("up"), 4, 1, f64::NAN, rng).is_none());
+    }
+}
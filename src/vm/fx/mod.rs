@@ -1,7 +1,9 @@
+mod arp;
 mod midi;
 mod pitch;
 mod prob;
 
+pub use self::arp::Arpeggiator;
 pub use self::midi::{MidiPitchMapper, MidiVelocityMapper};
 pub use self::pitch::PitchQuantizer;
 pub use self::prob::MarkovChain;
@@ -300,6 +300,7 @@ mod tests {
             onset: onset,
             dur: dur,
             value: EventValue::Trigger(val),
+            track: 0,
         }
     }
 
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::slice;
+
+use super::state::InterpState;
+use super::types::{InterpResult, Value};
+use crate::err::Error;
+
+/// An interpreter capable of evaluating quotations
+pub trait Apply {
+    /// Return a mutable reference to the interpreters internal state
+    fn state_mut(&mut self) -> &mut InterpState;
+
+    /// Evaluate a quotation with arguments, returning its result
+    fn apply(&mut self, quote: usize, args: &[Value]) -> Result<Value, Error>;
+}
+
+pub type Combinator = fn(&mut dyn Apply) -> InterpResult;
+
+/// Construct a list of the same kind as `like`
+fn collect(interp: &mut dyn Apply, like: &Value, vals: Vec<Value>) -> InterpResult {
+    let state = interp.state_mut();
+    let start = state.heap_len();
+    for val in vals {
+        state.heap_push(val);
+    }
+    let end = state.heap_len();
+    state.push(match *like {
+        Value::Seq(_, _) => Value::Seq(start, end),
+        Value::Group(_, _) => Value::Group(start, end),
+        _ => Value::List(start, end),
+    })
+}
+
+fn pop_list(interp: &mut dyn Apply) -> Result<(Value, Vec<Value>), Error> {
+    let state = interp.state_mut();
+    let lst = state.pop()?;
+    let (start, end) = lst.as_range()?;
+    let vals = (state.heap_slice_mut(start, end)?).to_vec();
+    Ok((lst, vals))
+}
+
+/// Evaluate a quotation, putting its result on the stack
+pub fn apply(interp: &mut dyn Apply) -> InterpResult {
+    let quote = (interp.state_mut().pop()?).as_quote()?;
+    let val = interp.apply(quote, &[])?;
+    interp.state_mut().push(val)
+}
+
+/// Apply a quotation to every element of a list
+pub fn map(interp: &mut dyn Apply) -> InterpResult {
+    let quote = (interp.state_mut().pop()?).as_quote()?;
+    let (lst, vals) = pop_list(interp)?;
+    let mut out = Vec::with_capacity(vals.len());
+    for val in vals {
+        out.push(interp.apply(quote, &[val])?);
+    }
+    collect(interp, &lst, out)
+}
+
+/// Keep only the elements of a list for which a quotation holds
+pub fn filter(interp: &mut dyn Apply) -> InterpResult {
+    let quote = (interp.state_mut().pop()?).as_quote()?;
+    let (lst, vals) = pop_list(interp)?;
+    let mut out = Vec::with_capacity(vals.len());
+    for val in vals {
+        if (interp.apply(quote, slice::from_ref(&val))?).as_bool() {
+            out.push(val);
+        }
+    }
+    collect(interp, &lst, out)
+}
+
+/// Reduce a list to a single value, from an initial accumulator
+pub fn fold(interp: &mut dyn Apply) -> InterpResult {
+    let quote = (interp.state_mut().pop()?).as_quote()?;
+    let mut acc = interp.state_mut().pop()?;
+    let (_, vals) = pop_list(interp)?;
+    for val in vals {
+        acc = interp.apply(quote, &[acc, val])?;
+    }
+    interp.state_mut().push(acc)
+}
+
+/// Evaluate a quotation 'n' times with the iteration count
+pub fn times(interp: &mut dyn Apply) -> InterpResult {
+    let quote = (interp.state_mut().pop()?).as_quote()?;
+    let n = interp.state_mut().pop_num()? as usize;
+    for i in 0..n {
+        let val = interp.apply(quote, &[Value::Number(i as f64)])?;
+        interp.state_mut().push(val)?;
+    }
+    Ok(None)
+}
+
+pub fn all() -> HashMap<&'static str, Combinator> {
+    let mut words: HashMap<&'static str, Combinator> = HashMap::new();
+    words.insert("apply", apply);
+    words.insert("filter", filter);
+    words.insert("fold", fold);
+    words.insert("map", map);
+    words.insert("times", times);
+    words
+}
@@ -1,9 +1,10 @@
 use std::collections::HashMap;
 use std::fmt::Write;
 
-use crate::err::Error;
+use crate::err::{Error, Span};
 use crate::lang::hash_str;
 
+use super::combinators::{self, Apply, Combinator};
 pub use super::stack::StackFrame;
 pub use super::state::InterpState;
 pub use super::types::{Instr, InterpResult, Value};
@@ -20,6 +21,9 @@ pub trait Interpreter<S> {
     /// Return a copy of the interpreters internal state
     fn state(&self) -> InterpState;
 
+    /// Return a mutable reference to the interpreters internal state
+    fn state_mut(&mut self) -> &mut InterpState;
+
     /// Return all the interpreters instructions
     fn instrs(&self) -> &[Instr];
 
@@ -77,6 +81,7 @@ pub struct BaseInterpreter<S> {
     state: InterpState,
     instrs: Vec<Instr>,
     words: HashMap<u64, Keyword<S>>,
+    combinators: HashMap<u64, Combinator>,
 }
 
 impl<S> BaseInterpreter<S> {
@@ -90,9 +95,15 @@ impl<S> BaseInterpreter<S> {
             words.insert(hash_str(word), *func);
         }
 
+        let mut combs = HashMap::new();
+        for (word, func) in &combinators::all() {
+            combs.insert(hash_str(word), *func);
+        }
+
         let mut interpreter = BaseInterpreter {
             instrs: instrs,
             words: words,
+            combinators: combs,
             data: data,
             state: InterpState::new(),
         };
@@ -104,6 +115,45 @@ impl<S> BaseInterpreter<S> {
     }
 }
 
+impl<S> Apply for BaseInterpreter<S> {
+    fn state_mut(&mut self) -> &mut InterpState {
+        &mut self.state
+    }
+
+    fn apply(&mut self, quote: usize, args: &[Value]) -> Result<Value, Error> {
+        // Quotations may read the variables of the word that evaluates them
+        let locals = match self.state.frames.last() {
+            Some(frame) => frame.locals.clone(),
+            None => HashMap::new(),
+        };
+
+        let depth = self.state.frames.len();
+        self.state.call(quote, 0, quote)?;
+        if let Some(frame) = self.state.frames.last_mut() {
+            frame.locals = locals;
+            for arg in args {
+                frame.push(arg.clone())?;
+            }
+        }
+
+        // Evaluate until the quotations frame has been returned from
+        loop {
+            self.state.pc += 1;
+            let pc = self.state.pc;
+            let instr = match self.instrs.get(pc) {
+                Some(instr) => *instr,
+                None => return Err(exception!()),
+            };
+            self.execute(pc, instr)?;
+            if self.state.frames.len() == depth {
+                break;
+            }
+        }
+
+        self.state.pop()
+    }
+}
+
 impl<S> Interpreter<S> for BaseInterpreter<S> {
     fn instrs(&self) -> &[Instr] {
         &self.instrs
@@ -113,6 +163,10 @@ impl<S> Interpreter<S> for BaseInterpreter<S> {
         self.state.clone()
     }
 
+    fn state_mut(&mut self) -> &mut InterpState {
+        &mut self.state
+    }
+
     fn data_mut(&mut self) -> &mut S {
         &mut self.data
     }
@@ -133,6 +187,17 @@ impl<S> Interpreter<S> for BaseInterpreter<S> {
 
             Instr::Return => self.state.ret(),
 
+            Instr::Jump(pc) => self.state.jump(pc),
+
+            Instr::Branch(pc) => {
+                // Fall through when the condition holds, otherwise jump
+                if (self.state.pop()?).as_bool() {
+                    Ok(None)
+                } else {
+                    self.state.jump(pc)
+                }
+            }
+
             Instr::StoreGlob(name) => {
                 let val = self.state.pop()?;
                 self.state.store_glob(name, val)?;
@@ -166,9 +231,18 @@ impl<S> Interpreter<S> for BaseInterpreter<S> {
                 Value::Group(start, end)
             }),
 
+            Instr::Quote(len) => {
+                // Push the quotation and skip over its body
+                self.state.push(Value::Quote(pc + 1))?;
+                self.state.pc += len;
+                Ok(None)
+            }
+
             Instr::Keyword(word) => {
                 // Keywords operate on an implicit stack frame
-                if let Some(func) = self.words.get(&word) {
+                if let Some(func) = self.combinators.get(&word).cloned() {
+                    func(self)
+                } else if let Some(func) = self.words.get(&word) {
                     func(&mut self.data, &mut self.state)
                 } else {
                     Err(error!(UnknownKeyword))
@@ -286,6 +360,10 @@ impl<S> Interpreter<S> for StackTraceInterpreter<S> {
         self.inner.state()
     }
 
+    fn state_mut(&mut self) -> &mut InterpState {
+        self.inner.state_mut()
+    }
+
     fn data_mut(&mut self) -> &mut S {
         self.inner.data_mut()
     }
@@ -303,10 +381,24 @@ impl<S> Interpreter<S> for StackTraceInterpreter<S> {
             Ok(val) => Ok(val),
             Err(err) => {
                 let mut trace = self.stack_trace();
-                if let Some(reason) = err.reason {
+                if let Some(ref reason) = err.reason {
                     write!(&mut trace, "\n{}", reason).ok();
                 }
-                Err(Error::with(err.kind, &trace))
+                let mut res = Error {
+                    reason: Some(trace),
+                    ..err
+                };
+                // Point at the token that was being evaluated
+                let state = self.inner.state();
+                if let Some((id, line, col)) = self.source_loc(state.pc as u64) {
+                    let width = state.strings.get(&id).map_or(0, |token| token.len());
+                    res = res.at(Span {
+                        line: line as usize,
+                        col: col as usize,
+                        len: width,
+                    });
+                }
+                Err(res)
             }
         }
     }
@@ -330,6 +422,71 @@ mod tests {
         assert_eq!(res.unwrap(), Value::Number(3.0));
     }
 
+    #[test]
+    fn test_branches() {
+        let instrs = vec![
+            Instr::Begin(hash_str("main")),
+            Instr::LoadVar(hash_str("cond")),
+            Instr::Branch(5),
+            Instr::LoadNumber(1.0),
+            Instr::Jump(6),
+            Instr::LoadNumber(2.0),
+            Instr::Return,
+            Instr::End(hash_str("main")),
+        ];
+        let mut interp = BaseInterpreter::new(instrs, &HashMap::new(), ());
+
+        interp
+            .state
+            .store_glob(hash_str("cond"), Value::Null)
+            .unwrap();
+        let res = interp.eval(1).unwrap();
+        assert_eq!(res.unwrap(), Value::Number(2.0));
+
+        interp.reset();
+        interp
+            .state
+            .store_glob(hash_str("cond"), Value::Number(3.0))
+            .unwrap();
+        let res = interp.eval(1).unwrap();
+        assert_eq!(res.unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_quotations() {
+        // [ 1 2 3 ] [: 2 multiply :] map
+        let instrs = vec![
+            Instr::Begin(hash_str("main")),
+            Instr::ListBegin,
+            Instr::LoadNumber(1.0),
+            Instr::LoadNumber(2.0),
+            Instr::LoadNumber(3.0),
+            Instr::ListEnd,
+            Instr::Quote(3),
+            Instr::LoadNumber(2.0),
+            Instr::Keyword(hash_str("multiply")),
+            Instr::Return,
+            Instr::Keyword(hash_str("map")),
+            Instr::Return,
+            Instr::End(hash_str("main")),
+        ];
+
+        let mut words: HashMap<&'static str, Keyword<()>> = HashMap::new();
+        words.insert("multiply", |_, state| {
+            let rhs = state.pop_num()?;
+            let lhs = state.pop_num()?;
+            state.push(Value::Number(lhs * rhs))
+        });
+
+        let mut interp = BaseInterpreter::new(instrs, &words, ());
+        let res = interp.eval(1).unwrap();
+        assert_eq!(res.unwrap(), Value::List(3, 6));
+        assert_eq!(
+            interp.state.heap_slice_mut(3, 6).unwrap(),
+            &[Value::Number(2.0), Value::Number(4.0), Value::Number(6.0)]
+        );
+    }
+
     #[test]
     fn test_block_zero() {
         let instrs = vec![
@@ -1,3 +1,4 @@
+pub mod combinators;
 mod interps;
 mod stack;
 mod state;
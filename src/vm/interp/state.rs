@@ -78,8 +78,9 @@ impl InterpState {
             }
         }
         self.frames.push(frame);
-        // Account for implicit increment of pc
-        self.pc = pc - 1;
+        // Account for implicit increment of pc, functions begin with a no-op
+        // so saturating is safe for the first function in a program
+        self.pc = pc.saturating_sub(1);
         Ok(None)
     }
 
@@ -107,6 +108,12 @@ impl InterpState {
         }
     }
 
+    pub fn jump(&mut self, pc: usize) -> InterpResult {
+        // Account for implicit increment of pc
+        self.pc = pc - 1;
+        Ok(None)
+    }
+
     pub fn last(&self) -> Result<Value, Error> {
         let frame = self.frame()?;
         Ok(frame.last()?)
@@ -151,6 +158,19 @@ impl InterpState {
         Ok(())
     }
 
+    /// Set a global variable between evaluations, keeping it over resets
+    pub fn set_glob(&mut self, name: u64, val: Value) -> Result<(), Error> {
+        match self.globals.get(&name) {
+            Some(&ptr) if ptr < self.reserved => self.heap[ptr] = val,
+            _ => {
+                self.heap.truncate(self.reserved);
+                self.store_glob(name, val)?;
+                self.reserved = self.heap_len();
+            }
+        };
+        Ok(())
+    }
+
     pub fn lookup(&mut self, name: u64) -> Result<Value, Error> {
         let ptr = match self.frame() {
             Ok(frame) => frame.locals.get(&name),
@@ -166,6 +186,31 @@ impl InterpState {
         }
     }
 
+    /// Describe a value in the syntax of the language, following lists
+    /// into the heap
+    pub fn describe(&self, val: &Value) -> String {
+        let items = |start: usize, end: usize| -> String {
+            let vals = self.heap.get(start..end).unwrap_or(&[]);
+            let vals: Vec<String> = vals.iter().map(|val| self.describe(val)).collect();
+            vals.join(" ")
+        };
+        match *val {
+            Value::Null => String::from("~"),
+            Value::Number(num) => format!("{}", num),
+            Value::Symbol(sym) => format!("'{:x}", sym),
+            Value::List(start, end) => format!("[{}]", items(start, end)),
+            Value::Group(start, end) => format!("{{{}}}", items(start, end)),
+            Value::Seq(start, end) => format!("({})", items(start, end)),
+            Value::Polymeter(start, end, steps) => {
+                format!("[{}] {} polymeter", items(start, end), steps)
+            }
+            Value::Str(ref string) => format!("\"{}\"", string),
+            Value::Instruction(instr) => format!("{:?}", instr),
+            Value::Curve(curve) => format!("{:?}", curve),
+            Value::Quote(pc) => format!("[: {} :]", pc),
+        }
+    }
+
     pub fn reset(&mut self) {
         self.exit = false;
         self.heap.truncate(self.reserved);
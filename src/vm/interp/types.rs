@@ -11,6 +11,9 @@ pub enum Instr {
     End(u64),
     Call(usize, usize),
     Return,
+    Jump(usize),
+    Branch(usize),
+    Quote(usize),
     LoadNumber(f64),
     LoadSymbol(u64),
     LoadVar(u64),
@@ -38,9 +41,12 @@ pub enum Value {
     List(usize, usize),
     Group(usize, usize),
     Seq(usize, usize),
+    // Sequences stepped together at the same rate, a number of steps a cycle
+    Polymeter(usize, usize, usize),
     Str(String),
     Instruction(Instr),
     Curve(Curve),
+    Quote(usize),
 }
 
 impl Value {
@@ -58,6 +64,29 @@ impl Value {
         }
     }
 
+    /// Interpret a value as a condition, only `~` and `0` are false
+    pub fn as_bool(&self) -> bool {
+        match *self {
+            Value::Null => false,
+            Value::Number(num) => num != 0.0,
+            _ => true,
+        }
+    }
+
+    pub fn as_quote(&self) -> Result<usize, Error> {
+        match *self {
+            Value::Quote(pc) => Ok(pc),
+            _ => Err(error!(InvalidArgs)),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, Error> {
+        match *self {
+            Value::Str(ref string) => Ok(string),
+            _ => Err(error!(InvalidArgs)),
+        }
+    }
+
     pub fn as_sym(&self) -> Result<u64, Error> {
         match *self {
             Value::Symbol(sym) => Ok(sym),
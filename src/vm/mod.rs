@@ -6,18 +6,22 @@ mod time;
 mod types;
 mod words;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::path::{Path, PathBuf};
 
 use crate::err::Error;
 use crate::lang::hash_str;
 
 use self::handler::{EventHandler, NoteInterceptor};
-use self::interp::{BaseInterpreter, Interpreter, StackTraceInterpreter};
+use self::interp::{combinators, BaseInterpreter, Interpreter, StackTraceInterpreter};
 pub use self::interp::{Instr, InterpState, Value};
 use self::time::Clock as InternalClock;
-pub use self::time::{millis_to_dur, Schedule};
-pub use self::types::{Command, Destination, Event, EventValue};
-use self::types::{SeqState, Track};
+pub use self::time::{dur_to_millis, millis_to_dur, Schedule, TimeSource, WallTime};
+pub use self::types::Track;
+pub use self::types::{Command, Destination, Event, EventValue, Params, PULSES_PER_BEAT};
+use self::types::{SeqState, BEATS_PER_BAR};
+pub use self::words::{docs, Doc};
 
 pub type Clock = InternalClock<Command>;
 
@@ -28,23 +32,46 @@ pub enum Status {
     Continue,
 }
 
+/// Names of the keywords built into the machine
+pub fn keywords() -> HashSet<&'static str> {
+    let mut keywords: HashSet<&'static str> = words::all().keys().cloned().collect();
+    keywords.extend(combinators::all().keys());
+    keywords
+}
+
 fn interpreter(
     instrs: &[Instr],
+    dir: Option<&Path>,
 ) -> Result<(HashMap<u64, usize>, Box<dyn Interpreter<SeqState>>), Error> {
     let mut interp = Box::new(StackTraceInterpreter::new(Box::new(BaseInterpreter::new(
         instrs.to_vec(),
         &words::all(),
         SeqState::new(),
     ))));
+    interp.data_mut().dir = dir.map(PathBuf::from);
+
+    // Tracks are seeded from the program's seed, which may be declared as
+    // a global variable
+    if let Ok(Value::Number(seed)) = interp.state().lookup(hash_str("seed")) {
+        interp.data_mut().seed = seed as u64;
+    }
 
     // Create tracks as defined by block 1 (the extension block)
     if let Some(val) = interp.eval_block(1)? {
         let (start, end) = val.as_range()?;
+        let seed = interp.data_mut().seed;
         let state = interp.state();
         for (i, ptr) in (start..end).enumerate() {
             let sym = (state.heap_get(ptr)?).as_sym()?;
             let data = interp.data_mut();
-            data.tracks.push(Track::new(i, sym));
+            data.tracks.push(Track::new(i, sym, seed));
+        }
+    }
+
+    // Initial tempo may be declared as a global variable
+    if let Ok(Value::Number(tempo)) = interp.state().lookup(hash_str("bpm")) {
+        if tempo > 0.0 {
+            interp.data_mut().tempo = tempo;
         }
     }
 
@@ -72,6 +99,52 @@ fn interpreter(
     Ok((funcs, interp))
 }
 
+/// Boundaries reloads are held back until
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Quantize {
+    Bars(f64),
+    // The longest revision of any track
+    Longest,
+}
+
+/// A program waiting to be swapped in at a boundary
+struct PendingReload {
+    id: usize,
+    boundary: f64,
+    queued: Option<f64>,
+    functions: HashMap<u64, usize>,
+    interp: Box<dyn Interpreter<SeqState>>,
+}
+
+/// Evaluate a function of a program in a fresh interpreter, describing each
+/// value of the list it returns. Global variables are first given the
+/// values in `globals`, and relative paths are found from `dir`
+pub fn evaluate(
+    instrs: &[Instr],
+    func: u64,
+    globals: &[(u64, Value)],
+    dir: Option<&Path>,
+) -> Result<Vec<String>, Error> {
+    let (funcs, mut interp) = self::interpreter(instrs, dir)?;
+    for (name, val) in globals {
+        interp.state_mut().set_glob(*name, val.clone())?;
+    }
+    let pc = match funcs.get(&func) {
+        Some(pc) => *pc,
+        None => return Err(error!(UnknownKeyword)),
+    };
+
+    let val = interp.eval(pc)?;
+    let state = interp.state();
+    match val {
+        Some(Value::List(start, end)) => (start..end)
+            .map(|ptr| Ok(state.describe(&state.heap_get(ptr)?)))
+            .collect(),
+        Some(val) => Ok(vec![state.describe(&val)]),
+        None => Ok(vec![]),
+    }
+}
+
 type Timer = Box<dyn FnMut(Schedule<Command>)>;
 type In = Box<dyn FnMut() -> Option<Command>>;
 type Out = Box<dyn FnMut(Command)>;
@@ -83,11 +156,32 @@ pub struct Machine {
     input: In,
     functions: HashMap<u64, usize>,
     handler: EventHandler,
+    tempo: f64,
+    scheduled_tempo: f64,
+    pulse: Option<f64>,
+    // Time MIDI stop is sent at, while every track is stopped
+    paused: Option<f64>,
+    cancel_stop: bool,
+    params: Params,
+    hot: bool,
+    quantize: Option<Quantize>,
+    reloads: usize,
+    pending: Option<PendingReload>,
+    // Directory of the program, kept for the programs it is reloaded with
+    dir: Option<PathBuf>,
 }
 
 impl Machine {
-    pub fn new(input: In, sink: Out, clock: Timer, instrs: &[Instr]) -> Result<Machine, Error> {
-        let (funcs, mut interp) = self::interpreter(instrs)?;
+    pub fn new(
+        input: In,
+        sink: Out,
+        clock: Timer,
+        instrs: &[Instr],
+        dir: Option<&Path>,
+    ) -> Result<Machine, Error> {
+        let (funcs, mut interp) = self::interpreter(instrs, dir)?;
+        let bpm = interp.data_mut().tempo;
+        let shared = interp.data_mut().params.clone();
         let mut cmds = vec![];
 
         for track in &interp.data_mut().tracks {
@@ -104,6 +198,17 @@ impl Machine {
             functions: funcs,
             interp: interp,
             handler: EventHandler::new(),
+            tempo: bpm,
+            scheduled_tempo: bpm,
+            pulse: None,
+            paused: None,
+            cancel_stop: false,
+            params: shared,
+            hot: false,
+            quantize: None,
+            reloads: 0,
+            pending: None,
+            dir: dir.map(PathBuf::from),
         };
 
         for cmd in &cmds {
@@ -113,12 +218,247 @@ impl Machine {
         Ok(machine)
     }
 
+    /// The tempo currently sounding
+    pub fn tempo(&self) -> f64 {
+        self.tempo
+    }
+
+    /// Change the tempo from the next revision of each track
+    pub fn set_tempo(&mut self, bpm: f64) {
+        if bpm > 0.0 {
+            self.interp.data_mut().tempo = bpm;
+        }
+    }
+
+    pub fn track(&mut self, func: u64) -> Option<&Track> {
+        let tracks = &self.interp.data_mut().tracks;
+        tracks.iter().find(|track| track.func == func)
+    }
+
+    /// Describe the value of a global variable
+    pub fn global(&mut self, name: u64) -> Option<String> {
+        let state = self.interp.state_mut();
+        match state.lookup(name) {
+            Ok(val) => Some(state.describe(&val)),
+            Err(_) => None,
+        }
+    }
+
+    /// Values of global variables that can be read outside the running
+    /// program, those that don't point into its heap or instructions
+    pub fn globals(&mut self) -> Vec<(u64, Value)> {
+        let state = self.interp.state_mut();
+        let mut globals: Vec<(u64, Value)> = state
+            .globals
+            .iter()
+            .filter_map(|(name, ptr)| match state.heap_get(*ptr) {
+                Ok(val @ Value::Null)
+                | Ok(val @ Value::Number(_))
+                | Ok(val @ Value::Symbol(_))
+                | Ok(val @ Value::Str(_))
+                | Ok(val @ Value::Curve(_)) => Some((*name, val)),
+                _ => None,
+            })
+            .collect();
+        globals.sort_by_key(|(name, _)| *name);
+        globals
+    }
+
+    /// Parameters shared with the host, read by tracks at each revision
+    pub fn params(&self) -> Params {
+        self.params.clone()
+    }
+
+    /// Keep running when a reload is requested, leaving the host to swap in
+    /// a new program with `reload`
+    pub fn hot_reload(&mut self) {
+        self.hot = true;
+    }
+
+    /// Apply reloads on the next multiple of `bars` rather than straight
+    /// away
+    pub fn quantize_reload(&mut self, bars: f64) {
+        self.quantize = if bars > 0.0 {
+            Some(Quantize::Bars(bars))
+        } else {
+            None
+        };
+    }
+
+    /// Apply reloads on the next multiple of the duration of the longest
+    /// track, so every track has finished its revision
+    pub fn quantize_reload_longest(&mut self) {
+        self.quantize = Some(Quantize::Longest);
+    }
+
+    /// Swap in a new program without stopping
+    ///
+    /// Tracks found in both programs keep their timing and revision, and
+    /// pick up their new definition from their next revision. Tracks whose
+    /// effects are set up with the same arguments keep their effects along
+    /// with any state they hold.
+    ///
+    /// When reloads are quantised the new program is held back until the
+    /// next boundary that no track has been evaluated past.
+    pub fn reload(&mut self, instrs: &[Instr]) -> Result<(), Error> {
+        let (funcs, next) = self::interpreter(instrs, self.dir.as_deref())?;
+        let data = self.interp.data_mut();
+        let times = data.tracks.iter().map(|track| track.real_time);
+
+        let period = match self.quantize {
+            Some(Quantize::Bars(bars)) => data.beats_to_millis(bars * BEATS_PER_BAR),
+            Some(Quantize::Longest) => data
+                .tracks
+                .iter()
+                .map(|track| track.duration)
+                .fold(0.0, f64::max),
+            None => 0.0,
+        };
+
+        // Without a period to wait for reloads apply straight away
+        if period <= 0.0 {
+            let start = times.fold(None, |start: Option<f64>, time| {
+                Some(start.map_or(time, |start| start.min(time)))
+            });
+            self.swap(funcs, next, start.unwrap_or(0.0));
+            return Ok(());
+        }
+
+        let latest = times.fold(0.0, f64::max);
+        self.reloads += 1;
+        self.pending = Some(PendingReload {
+            id: self.reloads,
+            boundary: (latest / period).ceil() * period,
+            queued: None,
+            functions: funcs,
+            interp: next,
+        });
+        self.queue_swap();
+        Ok(())
+    }
+
+    /// Queue a pending reload for when the first track is due to evaluate a
+    /// revision starting on or after the boundary
+    fn queue_swap(&mut self) {
+        let pending = match self.pending {
+            Some(ref mut pending) => pending,
+            None => return,
+        };
+
+        let tracks = &self.interp.data_mut().tracks;
+        let due = if tracks.is_empty() {
+            Some(pending.boundary)
+        } else {
+            tracks
+                .iter()
+                .filter(|track| track.real_time >= pending.boundary)
+                .map(|track| track.schedule_time)
+                .fold(None, |due: Option<f64>, time| {
+                    Some(due.map_or(time, |due| due.min(time)))
+                })
+        };
+
+        if let Some(due) = due {
+            if pending.queued.is_none() || Some(due) < pending.queued {
+                pending.queued = Some(due);
+                (self.clock)(Schedule::At(due, Command::Swap(pending.id)));
+            }
+        }
+    }
+
+    fn handle_swap_cmd(&mut self, id: usize) -> Result<Status, Error> {
+        // Later reloads replace earlier ones still waiting
+        match self.pending.take() {
+            Some(pending) if pending.id == id => {
+                self.swap(pending.functions, pending.interp, pending.boundary);
+            }
+            pending => self.pending = pending,
+        };
+        Ok(Status::Continue)
+    }
+
+    fn swap(
+        &mut self,
+        funcs: HashMap<u64, usize>,
+        mut interp: Box<dyn Interpreter<SeqState>>,
+        start: f64,
+    ) {
+        let previous = self.interp.data_mut();
+        let mut tracks = mem::take(&mut previous.tracks);
+
+        let data = interp.data_mut();
+        data.tempo = previous.tempo;
+        data.params = previous.params.clone();
+        data.midi_files = mem::take(&mut previous.midi_files);
+
+        for track in &mut data.tracks {
+            match tracks.iter().position(|old| old.func == track.func) {
+                Some(pos) => {
+                    let old = tracks.remove(pos);
+                    track.real_time = old.real_time;
+                    track.schedule_time = old.schedule_time;
+                    track.duration = old.duration;
+                    track.muted = old.muted;
+                    track.soloed = old.soloed;
+                    track.stopped = old.stopped;
+                    track.revision = old.revision;
+                    if data.seed == previous.seed {
+                        track.rng = old.rng;
+                    }
+                    if track.setup == old.setup {
+                        track.effects = old.effects;
+                    }
+                }
+                None => {
+                    // New tracks begin alongside the next revision to sound
+                    track.real_time = start;
+                    track.schedule_time = start;
+                    let cmd = Command::Track(track.id, 0, track.func);
+                    (self.clock)(Schedule::At(start, cmd));
+                }
+            }
+        }
+
+        self.functions = funcs;
+        self.interp = interp;
+    }
+
+    /// Start sending MIDI clock messages, synchronised to the tempo
+    pub fn midi_clock(&mut self) {
+        if self.pulse.is_none() {
+            self.pulse = Some(0.0);
+            (self.clock)(Schedule::At(0.0, Command::MidiStart));
+            (self.clock)(Schedule::At(0.0, Command::MidiClock));
+        }
+    }
+
     pub fn process(&mut self, cmd: Command) -> Result<Status, Error> {
         let status = match cmd {
-            Command::Stop => Ok(Status::Stop),
-            Command::Reload => Ok(Status::Reload),
+            Command::Stop => {
+                self.stop_midi_clock();
+                Ok(Status::Stop)
+            }
+            Command::Reload => {
+                if !self.hot {
+                    self.stop_midi_clock();
+                }
+                Ok(Status::Reload)
+            }
+            // A stop cancelled by a track starting before it was sent
+            Command::MidiStop if self.cancel_stop => {
+                self.cancel_stop = false;
+                Ok(Status::Continue)
+            }
             Command::Clock => self.handle_clock_cmd(),
-            Command::Track(num, rev, func) => self.handle_track_cmd(num, rev, func),
+            Command::MidiClock => self.handle_midi_clock_cmd(),
+            Command::Tempo(tempo) => {
+                self.tempo = tempo;
+                (self.sink)(cmd);
+                Ok(Status::Continue)
+            }
+            Command::Track(_, rev, func) => self.handle_track_cmd(rev, func),
+            Command::Swap(id) => self.handle_swap_cmd(id),
+            _ if cmd.is_control() => self.handle_control_cmd(cmd),
             _ => {
                 (self.sink)(cmd);
                 Ok(Status::Continue)
@@ -133,29 +473,189 @@ impl Machine {
     }
 
     fn handle_clock_cmd(&mut self) -> Result<Status, Error> {
-        if let Some(cmd) = (self.input)() {
+        while let Some(cmd) = (self.input)() {
             match cmd {
                 Command::Stop => {
+                    self.stop_midi_clock();
                     (self.clock)(Schedule::Stop);
                     return Ok(Status::Stop);
                 }
+                Command::Reload if self.hot => return Ok(Status::Reload),
                 Command::Reload => {
+                    self.stop_midi_clock();
                     (self.clock)(Schedule::Stop);
                     return Ok(Status::Reload);
                 }
+                Command::MidiStart | Command::MidiStop | Command::MidiContinue => {
+                    (self.sink)(cmd);
+                }
+                Command::Tempo(tempo) => {
+                    // Takes effect from the next revision of each track
+                    if tempo > 0.0 {
+                        self.interp.data_mut().tempo = tempo;
+                    }
+                }
+                _ if cmd.is_control() => {
+                    self.handle_control_cmd(cmd)?;
+                }
                 _ => return Err(exception!()),
             };
         }
         Ok(Status::Continue)
     }
 
-    fn handle_track_cmd(&mut self, num: usize, rev: usize, func: u64) -> Result<Status, Error> {
-        self.interp.data_mut().reset(rev);
+    fn handle_control_cmd(&mut self, cmd: Command) -> Result<Status, Error> {
+        match cmd {
+            Command::SetGlobal(name, val) => {
+                // Read by tracks from their next revision
+                let state = self.interp.state_mut();
+                state.set_glob(name, Value::Number(val))?;
+            }
+            Command::StartTrack(func) => self.start_track(func),
+            Command::Mute(func)
+            | Command::Unmute(func)
+            | Command::Solo(func)
+            | Command::Unsolo(func)
+            | Command::StopTrack(func) => {
+                for track in &mut self.interp.data_mut().tracks {
+                    if track.func != func {
+                        continue;
+                    }
+                    match cmd {
+                        Command::Mute(_) => track.muted = true,
+                        Command::Unmute(_) => track.muted = false,
+                        Command::Solo(_) => track.soloed = true,
+                        Command::Unsolo(_) => track.soloed = false,
+                        // Scheduled events play out, but no further
+                        // revisions are evaluated
+                        Command::StopTrack(_) => track.stopped = true,
+                        _ => return Err(exception!()),
+                    };
+                }
+                if let Command::StopTrack(_) = cmd {
+                    self.pause_midi_clock();
+                }
+            }
+            _ => return Err(exception!()),
+        };
+        Ok(Status::Continue)
+    }
+
+    /// Restart a stopped track from its first revision, alongside the next
+    /// revision of the tracks still running
+    fn start_track(&mut self, func: u64) {
+        let seed = self.interp.data_mut().seed;
+        let tracks = &mut self.interp.data_mut().tracks;
+        let start = tracks
+            .iter()
+            .filter(|track| !track.stopped)
+            .map(|track| track.real_time)
+            .fold(None, |start: Option<f64>, time| {
+                Some(start.map_or(time, |start| start.min(time)))
+            });
+
+        let mut restarted = None;
+        for track in tracks.iter_mut() {
+            if track.func == func && track.stopped {
+                let start = start.unwrap_or(track.real_time);
+                restarted = Some(start);
+                track.stopped = false;
+                track.revision = 0;
+                track.rng = Track::seeded(seed, track.func);
+                track.real_time = start;
+                track.schedule_time = start;
+                let cmd = Command::Track(track.id, 0, track.func);
+                (self.clock)(Schedule::At(start, cmd));
+            }
+        }
+
+        if let Some(start) = restarted {
+            self.resume_midi_clock(start);
+        }
+    }
+
+    /// Send MIDI stop once the last running track has played out. Clock
+    /// pulses carry on so devices stay in time until playback continues
+    fn pause_midi_clock(&mut self) {
+        let tracks = &self.interp.data_mut().tracks;
+        if self.pulse.is_none() || self.paused.is_some() {
+            return;
+        }
+        if tracks.iter().all(|track| track.stopped) {
+            let end = tracks
+                .iter()
+                .map(|track| track.real_time)
+                .fold(0.0, f64::max);
+            self.paused = Some(end);
+            (self.clock)(Schedule::At(end, Command::MidiStop));
+        }
+    }
+
+    /// Send MIDI continue when a track starts after every track was stopped
+    fn resume_midi_clock(&mut self, start: f64) {
+        if let Some(end) = self.paused.take() {
+            if start >= end {
+                (self.clock)(Schedule::At(start, Command::MidiContinue));
+            } else {
+                self.cancel_stop = true;
+            }
+        }
+    }
+
+    fn stop_midi_clock(&mut self) {
+        self.paused = None;
+        if self.pulse.take().is_some() {
+            (self.sink)(Command::MidiStop);
+        }
+    }
+
+    fn handle_midi_clock_cmd(&mut self) -> Result<Status, Error> {
+        let pulse = match self.pulse {
+            Some(pulse) => pulse,
+            None => return Ok(Status::Continue),
+        };
+
+        // Pulses are scheduled one at a time so tempo changes apply to the
+        // very next pulse
+        (self.sink)(Command::MidiClock);
+        let next = pulse + 60000.0 / (self.tempo * PULSES_PER_BEAT);
+        self.pulse = Some(next);
+        (self.clock)(Schedule::At(next, Command::MidiClock));
+        Ok(Status::Continue)
+    }
+
+    fn handle_track_cmd(&mut self, rev: usize, func: u64) -> Result<Status, Error> {
+        // Tracks are found by name as a reload may have moved or removed them
+        let num = match self
+            .interp
+            .data_mut()
+            .tracks
+            .iter()
+            .position(|t| t.func == func)
+        {
+            Some(num) => num,
+            None => return Ok(Status::Continue),
+        };
+
+        // Stopped tracks, and revisions left over from before a restart,
+        // are not evaluated
+        let track = &self.interp.data_mut().tracks[num];
+        if track.stopped || track.revision != rev {
+            return Ok(Status::Continue);
+        }
+
+        // Tracks draw random numbers from their own generator
+        let data = self.interp.data_mut();
+        data.rng = data.tracks[num].rng;
+        data.reset(rev);
         self.interp.reset();
         self.interp.eval(self.functions[&func])?;
 
         let data = self.interp.data_mut();
+        data.tracks[num].rng = data.rng;
+        let solo = data.tracks.iter().any(|track| track.soloed);
         let track = &mut data.tracks[num];
+        let audible = !track.muted && (track.soloed || !solo);
 
         for fx in &mut track.effects {
             data.events = fx.apply(data.duration, &data.events);
@@ -163,14 +663,29 @@ impl Machine {
 
         for event in &mut data.events {
             event.onset += track.real_time;
-            self.handler.handle(&mut self.clock, *event);
+            event.track = num;
+            // Muted tracks keep time without being heard
+            if audible {
+                self.handler.handle(&mut self.clock, *event);
+            }
+        }
+
+        // Tempo changes sound from the start of the revision that made them
+        if data.tempo != self.scheduled_tempo {
+            self.scheduled_tempo = data.tempo;
+            let cmd = Command::Tempo(data.tempo);
+            (self.clock)(Schedule::At(track.real_time, cmd));
         }
 
         // Tracks are scheduled one revision _ahead_ of the clock
+        track.duration = data.duration;
         track.real_time += data.duration;
         track.schedule_time += if rev == 0 { 0.0 } else { data.duration };
+        track.revision = rev + 1;
         let cmd = Command::Track(num, rev + 1, func);
         (self.clock)(Schedule::At(track.schedule_time, cmd));
+
+        self.queue_swap();
         Ok(Status::Continue)
     }
 }
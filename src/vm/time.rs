@@ -78,6 +78,35 @@ pub fn dur_to_millis(dur: Duration) -> f64 {
     secs + nanos
 }
 
+/// A source of elapsed time for driving a `Clock`
+pub trait TimeSource {
+    /// Time passed since the previous call
+    fn delta(&mut self) -> Duration;
+}
+
+/// Elapsed time as measured by the system clock
+#[derive(Debug)]
+pub struct WallTime {
+    previous: Instant,
+}
+
+impl WallTime {
+    pub fn new() -> WallTime {
+        WallTime {
+            previous: Instant::now(),
+        }
+    }
+}
+
+impl TimeSource for WallTime {
+    fn delta(&mut self) -> Duration {
+        let now = Instant::now();
+        let delta = now.duration_since(self.previous);
+        self.previous = now;
+        delta
+    }
+}
+
 #[derive(Debug)]
 pub struct Clock<T>
 where
@@ -140,14 +169,20 @@ where
             };
         }
 
+        // Nothing new can be due while time stands still
+        if delta == Duration::new(0, 0) {
+            return true;
+        }
+
         // Update elapsed time
         self.elapsed += delta;
         let elapsed = dur_to_millis(self.elapsed);
 
         // Process timers
         while let Some(timer) = self.next() {
+            // Timers are sent with the time they were scheduled for
             let expected = dur_to_millis(timer.t);
-            let event = Schedule::At(elapsed, timer.data);
+            let event = Schedule::At(expected, timer.data);
             self.output.send(event).ok();
 
             let error = (elapsed - expected).abs();
@@ -165,16 +200,12 @@ where
         true
     }
 
-    pub fn run_forever(&mut self) {
-        let mut previous = Instant::now();
+    pub fn run_with(&mut self, source: &mut dyn TimeSource) {
         let priority_time = millis_to_dur(1.5);
         let default_sleep = millis_to_dur(20.0);
 
         loop {
-            let now = Instant::now();
-            let delta = now.duration_since(previous);
-            previous = now;
-
+            let delta = source.delta();
             if !self.tick(delta) {
                 break;
             }
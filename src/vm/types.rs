@@ -1,10 +1,21 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
 use rand::{SeedableRng, StdRng};
 use serde::Serialize;
 
+use crate::lang::hash_str;
+use crate::smf::Note;
+
 use super::interp::{InterpResult, InterpState};
 use super::math::Curve;
 use super::time::Priority;
 
+pub const DEFAULT_TEMPO: f64 = 120.0;
+pub const BEATS_PER_BAR: f64 = 4.0;
+pub const PULSES_PER_BEAT: f64 = 24.0;
+
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Eq)]
 pub enum Destination {
     Midi(u8, u8),
@@ -22,6 +33,8 @@ pub struct Event {
     pub onset: f64,
     pub dur: f64,
     pub value: EventValue,
+    #[serde(skip)]
+    pub track: usize,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize)]
@@ -30,9 +43,22 @@ pub enum Command {
     MidiCtl(u8, u8, u8),
     MidiNoteOff(u8, u8),
     MidiNoteOn(u8, u8, u8),
+    MidiClock,
+    MidiStart,
+    MidiContinue,
+    MidiStop,
     Stop,
     Reload,
     Clock,
+    Tempo(f64),
+    SetGlobal(u64, f64),
+    Mute(u64),
+    Unmute(u64),
+    Solo(u64),
+    Unsolo(u64),
+    StopTrack(u64),
+    StartTrack(u64),
+    Swap(usize),
     Track(usize, usize, u64),
 }
 
@@ -43,14 +69,43 @@ impl Priority for Command {
             Command::Stop => 1,
             Command::Reload => 2,
             Command::Clock => 3,
-            Command::Track(_, _, _) => 4,
-            Command::Event(_) => 5,
-            Command::MidiNoteOn(_, _, _) => 6,
-            Command::MidiCtl(_, _, _) => 7,
+            Command::Tempo(_) => 4,
+            Command::SetGlobal(_, _) => 5,
+            Command::Mute(_) => 6,
+            Command::Unmute(_) => 7,
+            Command::Solo(_) => 8,
+            Command::Unsolo(_) => 9,
+            Command::StopTrack(_) => 10,
+            Command::StartTrack(_) => 11,
+            Command::Swap(_) => 12,
+            Command::MidiStop => 13,
+            Command::MidiStart => 14,
+            Command::MidiContinue => 15,
+            Command::MidiClock => 16,
+            Command::Track(_, _, _) => 17,
+            Command::Event(_) => 18,
+            Command::MidiNoteOn(_, _, _) => 19,
+            Command::MidiCtl(_, _, _) => 20,
         }
     }
 }
 
+impl Command {
+    /// Commands that change globals or tracks of a running program
+    pub fn is_control(&self) -> bool {
+        matches!(
+            *self,
+            Command::SetGlobal(_, _)
+                | Command::Mute(_)
+                | Command::Unmute(_)
+                | Command::Solo(_)
+                | Command::Unsolo(_)
+                | Command::StopTrack(_)
+                | Command::StartTrack(_)
+        )
+    }
+}
+
 pub trait Effect {
     fn apply(&mut self, dur: f64, events: &[Event]) -> Vec<Event>;
 }
@@ -58,24 +113,74 @@ pub trait Effect {
 pub type Result = InterpResult;
 pub type Keyword = fn(&mut SeqState, &mut InterpState) -> InterpResult;
 
+/// Values written by a host application while a program runs, shared with
+/// the machine and read by tracks as they are evaluated
+#[derive(Clone, Debug, Default)]
+pub struct Params {
+    values: Arc<RwLock<HashMap<u64, f64>>>,
+}
+
+impl Params {
+    pub fn new() -> Params {
+        Params::default()
+    }
+
+    pub fn set(&self, name: &str, val: f64) {
+        self.values.write().unwrap().insert(hash_str(name), val);
+    }
+
+    pub fn get(&self, name: u64) -> Option<f64> {
+        self.values.read().unwrap().get(&name).cloned()
+    }
+}
+
 pub struct Track {
     pub id: usize,
     pub func: u64,
     pub effects: Vec<Box<dyn Effect>>,
+    // Arguments each effect was created with, to tell which effects are
+    // unchanged when a program is reloaded
+    pub setup: Vec<Vec<u64>>,
     pub real_time: f64,
     pub schedule_time: f64,
+    // Length of the latest revision
+    pub duration: f64,
+    pub muted: bool,
+    pub soloed: bool,
+    pub stopped: bool,
+    pub revision: usize,
+    pub rng: StdRng,
 }
 
 impl Track {
-    pub fn new(id: usize, func: u64) -> Track {
+    pub fn new(id: usize, func: u64, seed: u64) -> Track {
         Track {
             id: id,
             func: func,
             effects: Vec::new(),
+            setup: Vec::new(),
             real_time: 0.0,
             schedule_time: 0.0,
+            duration: 0.0,
+            muted: false,
+            soloed: false,
+            stopped: false,
+            revision: 0,
+            rng: Track::seeded(seed, func),
         }
     }
+
+    /// Add an effect, along with the arguments it was set up with
+    pub fn add_effect(&mut self, fx: Box<dyn Effect>, args: &[u64]) {
+        self.effects.push(fx);
+        self.setup.push(args.to_vec());
+    }
+
+    /// A random number generator for a track, the same for every track of
+    /// the same name in programs with the same seed
+    pub fn seeded(seed: u64, func: u64) -> StdRng {
+        StdRng::from_seed(&[seed as usize, func as usize])
+    }
 }
 
 pub struct SeqState {
@@ -83,7 +188,13 @@ pub struct SeqState {
     pub events: Vec<Event>,
     pub tracks: Vec<Track>,
     pub duration: f64,
+    pub tempo: f64,
+    pub seed: u64,
     pub rng: StdRng,
+    pub midi_files: HashMap<String, Vec<Vec<Note>>>,
+    // Directory relative paths are found from, the program's when known
+    pub dir: Option<PathBuf>,
+    pub params: Params,
 }
 
 impl SeqState {
@@ -93,10 +204,20 @@ impl SeqState {
             events: Vec::new(),
             tracks: Vec::new(),
             duration: 0.0,
+            tempo: DEFAULT_TEMPO,
+            seed: 0,
             rng: StdRng::from_seed(&[0, 0, 0, 0]),
+            midi_files: HashMap::new(),
+            dir: None,
+            params: Params::new(),
         }
     }
 
+    /// Convert a duration in beats to milliseconds at the current tempo
+    pub fn beats_to_millis(&self, beats: f64) -> f64 {
+        beats * 60000.0 / self.tempo
+    }
+
     pub fn reset(&mut self, rev: usize) {
         self.revision = rev;
         self.duration = 0.0;
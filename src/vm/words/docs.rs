@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+/// Documentation for a built-in keyword or combinator
+///
+/// Stack effects list the values taken from the stack and the values put
+/// back, the top of the stack last, as `name:type`. A trailing `?` marks a
+/// value that may not be put back and `*` a value that may be repeated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Doc {
+    pub effect: &'static str,
+    pub summary: &'static str,
+}
+
+#[rustfmt::skip]
+const DOCS: &[(&str, &str, &str)] = &[
+    // bin
+    ("bin_list", "( length:num value:num -- seq )", "Encode a number into a binary list"),
+    ("gray_code", "( value:num -- num )", "Gray code number encoding"),
+    // combinators
+    ("apply", "( body:quote -- value:any )", "Evaluate a quotation, putting its result on the stack"),
+    ("filter", "( values:list test:quote -- list )", "Keep only the elements of a list for which a quotation holds"),
+    ("fold", "( values:list init:any body:quote -- value:any )", "Reduce a list to a single value, from an initial accumulator"),
+    ("map", "( values:list body:quote -- list )", "Apply a quotation to every element of a list"),
+    ("times", "( n:num body:quote -- value:any* )", "Evaluate a quotation 'n' times with the iteration count"),
+    // curve
+    ("linear", "( points:list -- curve )", "Create a bezier curve from a linear ramp"),
+    // debug
+    ("print", "( value:any -- value:any )", "Print the value on top of the stack"),
+    ("print_heap", "( values:list -- )", "Print the contents of a list"),
+    // fx
+    ("pitch_quantizer", "( track:sym key:sym octave:num scale:sym -- )", "Quantize the pitches of a track to a scale"),
+    ("markov_chain", "( track:sym order:num capacity:num -- )", "Assign a markov chain to a track"),
+    ("arpeggiate", "( track:sym mode:sym rate:num octaves:num gate:num -- )", "Spread the notes of chords played by a track over time, in mode 'up, 'down, 'up_down, 'random or 'as_played"),
+    ("midi_velocity_mapper", "( track:sym device:sym param:sym -- )", "Map the velocity of a track's notes to a device parameter"),
+    ("midi_pitch_mapper", "( track:sym device:sym param:sym -- )", "Map the pitch of a track's notes to a device parameter"),
+    // list
+    ("cycle", "( values:list -- value:any? )", "Every cycle, puts the 'next' element of a list on the stack"),
+    ("degrade", "( values:list -- values:list )", "Randomly set values to rests in a list"),
+    ("every", "( then:any else:any period:num -- value:any )", "Put a value on the stack every 'n' cycles"),
+    ("palindrome", "( values:list -- values:list )", "Reverse a list every other cycle"),
+    ("range", "( start:num end:num -- seq )", "Construct a continuous integer sequence from `start` to `end`"),
+    ("repeat", "( value:any times:num -- value:any* )", "Repeat a value 'n' times"),
+    ("reverse", "( values:list -- values:list )", "Reverse a list, leaving it on the stack"),
+    ("rotate", "( values:list amount:num -- values:list )", "Rotate a list"),
+    ("shuffle", "( values:list -- values:list )", "Shuffle a list, leaving it on the stack"),
+    // logic
+    ("and", "( lhs:any rhs:any -- num )", "Logical conjunction of two conditions"),
+    ("eq", "( lhs:any rhs:any -- num )", "Test two values for equality"),
+    ("gt", "( lhs:num rhs:num -- num )", "Test if a number is greater than another"),
+    ("lt", "( lhs:num rhs:num -- num )", "Test if a number is less than another"),
+    ("not", "( value:any -- num )", "Logical negation of a condition"),
+    ("or", "( lhs:any rhs:any -- num )", "Logical disjunction of two conditions"),
+    // math
+    ("add", "( lhs:num rhs:num -- num )", "Add two numbers"),
+    ("divide", "( lhs:num rhs:num -- num )", "Divide a number by another"),
+    ("modulo", "( lhs:num rhs:num -- num )", "Remainder of dividing a number by another"),
+    ("multiply", "( lhs:num rhs:num -- num )", "Multiply two numbers"),
+    ("subtract", "( lhs:num rhs:num -- num )", "Subtract a number from another"),
+    // midi
+    ("midi_file", "( path:str track:num -- seq )", "Put the notes of a track in a midi file, found next to the program, on the stack"),
+    ("midi_out", "( values:any dur:num chan:num -- )", "Output midi events"),
+    // prob
+    ("brownian", "( start:num step:num len:num -- seq )", "Generate a random walk of values moving by at most a step"),
+    ("chance", "( value:any prob:num -- any )", "Keep a value with a probability, otherwise replace it with a rest"),
+    ("exponential", "( rate:num -- num )", "Push a random number from an exponential distribution onto the stack"),
+    ("gaussian", "( mean:num deviation:num -- num )", "Push a random number from a normal distribution onto the stack"),
+    ("rand_range", "( min:num max:num -- num )", "Push a random integer, within a range, onto the stack"),
+    ("rand_seed", "( seed:num -- )", "Seed the random number generator of the track, otherwise seeded from its name and the '@seed' global"),
+    ("wchoose", "( values:list weights:list -- any )", "Choose a value from a list at random, weighted by a list of weights"),
+    // rhythm
+    ("complement", "( values:list -- seq )", "Swap the onsets and rests of a rhythm"),
+    ("euclid", "( onsets:num pulses:num rotation:num -- seq )", "Generate a Euclidean rhythm of onsets and rests"),
+    ("hop_jump", "( onsets:num pulses:num hop:num -- seq )", "Generate a rhythm using the Hop-and-jump algorithm"),
+    ("inter_onset", "( values:list -- seq )", "Return a new list containing the difference between consecutive elements"),
+    ("necklace", "( values:list -- seq )", "Rotate a rhythm to its canonical form, starting on an onset"),
+    ("onsets", "( start:num end:num values:list -- seq )", "Return a binary onset representation of a list"),
+    ("polymeter", "( values:list steps:num -- any )", "Play sequences at the same rate, for a number of steps a cycle"),
+    ("polyrhythm", "( values:list -- list )", "Play sequences in the same span of time, each dividing it evenly"),
+    // set
+    ("intersection", "( a:list b:list -- seq )", "Perform the intersection ('or') of two lists"),
+    ("sieve", "( values:list modulus:num shift:num -- seq )", "Apply a residual class to an integer sequence"),
+    ("symmetric_difference", "( a:list b:list -- seq )", "Perform the symmetric difference ('xor') between two lists"),
+    ("union", "( a:list b:list -- seq )", "Perform the union ('and') of two lists"),
+    // stack
+    ("drop", "( value:any -- )", "Remove the value on top of the stack"),
+    ("dup", "( value:any -- value:any value:any )", "Duplicate the value on top of the stack"),
+    ("swap", "( a:any b:any -- b:any a:any )", "Swap the two values on top of the stack"),
+    // tempo
+    ("bars", "( bars:num -- num )", "Convert a number of bars to milliseconds at the current tempo"),
+    ("beats", "( beats:num -- num )", "Convert a number of beats to milliseconds at the current tempo"),
+    ("bpm", "( tempo:num -- )", "Set the tempo in beats per minute"),
+    // track
+    ("param", "( name:sym -- num )", "Puts the value of a host parameter onto the stack, or zero when unset"),
+    ("revision", "( -- num )", "Puts the current cycle revision onto the stack"),
+];
+
+pub fn docs() -> HashMap<&'static str, Doc> {
+    DOCS.iter()
+        .map(|&(name, effect, summary)| (name, Doc { effect, summary }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::interp::combinators;
+    use crate::vm::words::all;
+
+    #[test]
+    fn test_every_keyword_documented() {
+        let mut words: Vec<&str> = all().keys().cloned().collect();
+        words.extend(combinators::all().keys());
+        let mut documented: Vec<&str> = docs().keys().cloned().collect();
+        words.sort();
+        documented.sort();
+        assert_eq!(words, documented);
+    }
+}
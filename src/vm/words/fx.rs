@@ -1,4 +1,7 @@
-use crate::vm::fx::{MarkovChain, MidiPitchMapper, MidiVelocityMapper, PitchQuantizer};
+use crate::lang::hash_str;
+use crate::vm::fx::{
+    Arpeggiator, MarkovChain, MidiPitchMapper, MidiVelocityMapper, PitchQuantizer,
+};
 use crate::vm::interp::InterpState;
 use crate::vm::types::{Result, SeqState};
 
@@ -22,7 +25,41 @@ pub fn pitch_quantizer(seq: &mut SeqState, state: &mut InterpState) -> Result {
         None => return Err(error!(InvalidArgs)),
     };
 
-    track.effects.push(Box::new(fx));
+    let args = [hash_str("pitch_quantizer"), key, octave as u64, scale];
+    track.add_effect(Box::new(fx), &args);
+    Ok(None)
+}
+
+/// Spread the notes of chords played by a track over time
+pub fn arpeggiate(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let gate = state.pop_num()?;
+    let octaves = state.pop_num()? as usize;
+    let rate = state.pop_num()? as usize;
+    let mode = (state.pop()?).as_sym()?;
+    let sym = (state.pop()?).as_sym()?;
+
+    let track = match seq
+        .tracks
+        .iter_mut()
+        .find(|ref mut track| track.func == sym)
+    {
+        Some(track) => track,
+        None => return Err(error!(InvalidArgs)),
+    };
+
+    let fx = match Arpeggiator::new(mode, rate, octaves, gate, seq.rng) {
+        Some(fx) => fx,
+        None => return Err(error!(InvalidArgs)),
+    };
+
+    let args = [
+        hash_str("arpeggiate"),
+        mode,
+        rate as u64,
+        octaves as u64,
+        gate.to_bits(),
+    ];
+    track.add_effect(Box::new(fx), &args);
     Ok(None)
 }
 
@@ -43,7 +80,8 @@ pub fn markov_chain(seq: &mut SeqState, state: &mut InterpState) -> Result {
     {
         Some(track) => {
             let fx = MarkovChain::new(order, capacity, seq.rng);
-            track.effects.push(Box::new(fx));
+            let args = [hash_str("markov_chain"), order as u64, capacity as u64];
+            track.add_effect(Box::new(fx), &args);
             Ok(None)
         }
         None => Err(error!(InvalidArgs)),
@@ -64,8 +102,9 @@ pub fn midi_velocity_mapper(seq: &mut SeqState, state: &mut InterpState) -> Resu
         None => return Err(error!(InvalidArgs)),
     };
 
+    let args = [hash_str("midi_velocity_mapper"), device, param];
     match MidiVelocityMapper::new(device, param) {
-        Some(fx) => track.effects.push(Box::new(fx)),
+        Some(fx) => track.add_effect(Box::new(fx), &args),
         None => return Err(error!(InvalidArgs)),
     };
 
@@ -86,8 +125,9 @@ pub fn midi_pitch_mapper(seq: &mut SeqState, state: &mut InterpState) -> Result
         None => return Err(error!(InvalidArgs)),
     };
 
+    let args = [hash_str("midi_pitch_mapper"), device, param];
     match MidiPitchMapper::new(device, param) {
-        Some(fx) => track.effects.push(Box::new(fx)),
+        Some(fx) => track.add_effect(Box::new(fx), &args),
         None => return Err(error!(InvalidArgs)),
     };
 
@@ -0,0 +1,90 @@
+use crate::vm::interp::{InterpState, Value};
+use crate::vm::types::{Result, SeqState};
+
+fn push_bool(state: &mut InterpState, val: bool) -> Result {
+    state.push(Value::Number(if val { 1.0 } else { 0.0 }))?;
+    Ok(None)
+}
+
+/// Test two values for equality
+pub fn equal(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let rhs = state.pop()?;
+    let lhs = state.pop()?;
+    push_bool(state, lhs == rhs)
+}
+
+/// Test if a number is less than another
+pub fn less_than(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let rhs = state.pop_num()?;
+    let lhs = state.pop_num()?;
+    push_bool(state, lhs < rhs)
+}
+
+/// Test if a number is greater than another
+pub fn greater_than(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let rhs = state.pop_num()?;
+    let lhs = state.pop_num()?;
+    push_bool(state, lhs > rhs)
+}
+
+/// Logical conjunction of two conditions
+pub fn and(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let rhs = (state.pop()?).as_bool();
+    let lhs = (state.pop()?).as_bool();
+    push_bool(state, lhs && rhs)
+}
+
+/// Logical disjunction of two conditions
+pub fn or(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let rhs = (state.pop()?).as_bool();
+    let lhs = (state.pop()?).as_bool();
+    push_bool(state, lhs || rhs)
+}
+
+/// Logical negation of a condition
+pub fn not(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let val = (state.pop()?).as_bool();
+    push_bool(state, !val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comparisons() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        state.call(0, 0, 1).unwrap();
+        state.push(Value::Number(2.0)).unwrap();
+        state.push(Value::Number(3.0)).unwrap();
+        less_than(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 1.0);
+        state.push(Value::Number(2.0)).unwrap();
+        state.push(Value::Number(3.0)).unwrap();
+        greater_than(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 0.0);
+        state.push(Value::Symbol(7)).unwrap();
+        state.push(Value::Symbol(7)).unwrap();
+        equal(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        state.call(0, 0, 1).unwrap();
+        state.push(Value::Number(1.0)).unwrap();
+        state.push(Value::Null).unwrap();
+        and(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 0.0);
+        state.push(Value::Number(1.0)).unwrap();
+        state.push(Value::Null).unwrap();
+        or(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 1.0);
+        state.push(Value::Number(0.0)).unwrap();
+        not(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 1.0);
+    }
+}
@@ -1,6 +1,61 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::smf;
 use crate::vm::interp::{InterpState, Value};
 use crate::vm::types::{Destination, Event, EventValue, Result, SeqState};
 
+/// Put the notes of a track in a midi file on the stack, as a sequence of
+/// pitches with notes that start together grouped
+pub fn midi_file(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let track = state.pop_num()? as usize;
+    let path = state.pop()?;
+    let path = path.as_str()?;
+
+    // Files are only read once, and not on every revision
+    if !seq.midi_files.contains_key(path) {
+        // Relative paths are found next to the program
+        let file = match seq.dir {
+            Some(ref dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+        let data = fs::read(file)?;
+        seq.midi_files.insert(path.to_string(), smf::read(&data)?);
+    }
+
+    let notes = match seq.midi_files[path].get(track) {
+        Some(notes) => notes,
+        None => return Err(error!(InvalidArgs, "Unknown midi file track")),
+    };
+
+    let mut steps = vec![];
+    let mut i = 0;
+    while i < notes.len() {
+        let tick = notes[i].tick;
+        let start = state.heap_len();
+        while i < notes.len() && notes[i].tick == tick {
+            state.heap_push(Value::Number(f64::from(notes[i].pitch)));
+            i += 1;
+        }
+
+        let end = state.heap_len();
+        steps.push(if end - start == 1 {
+            state.heap_get(start)?
+        } else {
+            Value::Group(start, end)
+        });
+    }
+
+    let start = state.heap_len();
+    for step in steps {
+        state.heap_push(step);
+    }
+
+    let end = state.heap_len();
+    state.push(Value::Seq(start, end))?;
+    Ok(None)
+}
+
 /// Output midi events
 pub fn midi_out(seq: &mut SeqState, state: &mut InterpState) -> Result {
     let chan = state.pop_num()? as u8;
@@ -22,6 +77,7 @@ pub fn midi_out(seq: &mut SeqState, state: &mut InterpState) -> Result {
                     onset: onset,
                     dur: dur,
                     value: EventValue::Curve(points),
+                    track: 0,
                 };
                 output.push(event);
             }
@@ -32,6 +88,7 @@ pub fn midi_out(seq: &mut SeqState, state: &mut InterpState) -> Result {
                     onset: onset,
                     dur: dur,
                     value: EventValue::Trigger(val),
+                    track: 0,
                 };
                 output.push(event);
             }
@@ -48,6 +105,22 @@ pub fn midi_out(seq: &mut SeqState, state: &mut InterpState) -> Result {
                     visit.push((onset, dur, state.heap_get(n)?));
                 }
             }
+            Value::Polymeter(start, end, steps) => {
+                let interval = dur / steps as f64;
+                for n in start..end {
+                    let (first, last) = (state.heap_get(n)?).as_range()?;
+                    let len = last - first;
+                    if len == 0 {
+                        continue;
+                    }
+                    // Steps carry on from where the last revision left off
+                    let offset = seq.revision * steps;
+                    for step in 0..steps {
+                        let val = state.heap_get(first + (offset + step) % len)?;
+                        visit.push((onset + step as f64 * interval, interval, val));
+                    }
+                }
+            }
             Value::List(start, end) => {
                 let len = end - start;
                 if len == 0 || len > 3 {
@@ -78,6 +151,7 @@ pub fn midi_out(seq: &mut SeqState, state: &mut InterpState) -> Result {
                     onset: onset,
                     dur: dur,
                     value: value,
+                    track: 0,
                 });
             }
             _ => return Err(error!(InvalidArgs)),
@@ -114,18 +188,21 @@ mod tests {
                     onset: 0.0,
                     dur: 1000.0,
                     value: EventValue::Trigger(3.0),
+                    track: 0,
                 },
                 Event {
                     dest: Destination::Midi(0, 127),
                     onset: 0.0,
                     dur: 1000.0,
                     value: EventValue::Trigger(2.0),
+                    track: 0,
                 },
                 Event {
                     dest: Destination::Midi(0, 127),
                     onset: 0.0,
                     dur: 1000.0,
                     value: EventValue::Trigger(1.0),
+                    track: 0,
                 },
             ]
         );
@@ -1,20 +1,25 @@
 mod bin;
 mod curve;
 mod debug;
+mod docs;
 mod fx;
 mod list;
+mod logic;
 mod math;
 mod midi;
 mod prob;
 mod rhythm;
 mod set;
 mod stack;
+mod tempo;
 mod track;
 
 use std::collections::HashMap;
 
 use crate::vm::types::Keyword;
 
+pub use self::docs::{docs, Doc};
+
 type Module = HashMap<&'static str, Keyword>;
 
 fn bin(words: &mut Module) {
@@ -34,6 +39,7 @@ fn debug(words: &mut Module) {
 fn fx(words: &mut Module) {
     words.insert("pitch_quantizer", fx::pitch_quantizer);
     words.insert("markov_chain", fx::markov_chain);
+    words.insert("arpeggiate", fx::arpeggiate);
     words.insert("midi_velocity_mapper", fx::midi_velocity_mapper);
     words.insert("midi_pitch_mapper", fx::midi_pitch_mapper);
 }
@@ -50,6 +56,15 @@ fn list(words: &mut Module) {
     words.insert("shuffle", list::shuffle);
 }
 
+fn logic(words: &mut Module) {
+    words.insert("and", logic::and);
+    words.insert("eq", logic::equal);
+    words.insert("gt", logic::greater_than);
+    words.insert("lt", logic::less_than);
+    words.insert("not", logic::not);
+    words.insert("or", logic::or);
+}
+
 fn math(words: &mut Module) {
     words.insert("add", math::add);
     words.insert("divide", math::divide);
@@ -59,18 +74,29 @@ fn math(words: &mut Module) {
 }
 
 fn midi(words: &mut Module) {
+    words.insert("midi_file", midi::midi_file);
     words.insert("midi_out", midi::midi_out);
 }
 
 fn prob(words: &mut Module) {
+    words.insert("brownian", prob::brownian);
+    words.insert("chance", prob::chance);
+    words.insert("exponential", prob::exponential);
+    words.insert("gaussian", prob::gaussian);
     words.insert("rand_range", prob::rand_range);
     words.insert("rand_seed", prob::rand_seed);
+    words.insert("wchoose", prob::wchoose);
 }
 
 fn rhythm(words: &mut Module) {
+    words.insert("complement", rhythm::complement);
+    words.insert("euclid", rhythm::euclid);
     words.insert("hop_jump", rhythm::hop_jump);
     words.insert("inter_onset", rhythm::inter_onset);
+    words.insert("necklace", rhythm::necklace);
     words.insert("onsets", rhythm::onsets);
+    words.insert("polymeter", rhythm::polymeter);
+    words.insert("polyrhythm", rhythm::polyrhythm);
 }
 
 fn set(words: &mut Module) {
@@ -86,7 +112,14 @@ fn stack(words: &mut Module) {
     words.insert("swap", stack::swap);
 }
 
+fn tempo(words: &mut Module) {
+    words.insert("bars", tempo::bars);
+    words.insert("beats", tempo::beats);
+    words.insert("bpm", tempo::bpm);
+}
+
 fn track(words: &mut Module) {
+    words.insert("param", track::param);
     words.insert("revision", track::revision);
 }
 
@@ -97,12 +130,14 @@ pub fn all() -> Module {
     debug(&mut words);
     fx(&mut words);
     list(&mut words);
+    logic(&mut words);
     math(&mut words);
     midi(&mut words);
     prob(&mut words);
     rhythm(&mut words);
     set(&mut words);
     stack(&mut words);
+    tempo(&mut words);
     track(&mut words);
     words
 }
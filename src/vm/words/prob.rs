@@ -1,4 +1,5 @@
 use byteorder::{LittleEndian, WriteBytesExt};
+use rand::distributions::{Exp, IndependentSample, Normal};
 use rand::{Rng, SeedableRng};
 
 use crate::vm::interp::{InterpState, Value};
@@ -13,7 +14,7 @@ pub fn rand_range(seq: &mut SeqState, state: &mut InterpState) -> Result {
     Ok(None)
 }
 
-/// Seed the random number generator
+/// Seed the random number generator of the track
 pub fn rand_seed(seq: &mut SeqState, state: &mut InterpState) -> Result {
     let seed = state.pop_num()? as i64;
     let mut wtr = vec![];
@@ -25,6 +26,108 @@ pub fn rand_seed(seq: &mut SeqState, state: &mut InterpState) -> Result {
     Ok(None)
 }
 
+/// Keep a value with a probability, otherwise replace it with a rest
+pub fn chance(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let prob = state.pop_num()?;
+    let val = state.pop()?;
+    if seq.rng.next_f64() < prob {
+        state.push(val)?;
+    } else {
+        state.push(Value::Null)?;
+    }
+    Ok(None)
+}
+
+/// Choose a value from a list at random, weighted by a list of weights
+#[allow(clippy::neg_cmp_op_on_partial_ord)]
+pub fn wchoose(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let (wstart, wend) = (state.pop()?).as_range()?;
+    let (start, end) = (state.pop()?).as_range()?;
+    if wend - wstart != end - start {
+        return Err(error!(InvalidArgs, "Expected a weight for every value"));
+    }
+
+    let mut weights = Vec::with_capacity(wend - wstart);
+    for ptr in wstart..wend {
+        let weight = (state.heap_get(ptr)?).as_num()?;
+        // NaN fails the comparison, and would make every choice the last weighted value
+        if !(weight >= 0.0) {
+            return Err(error!(InvalidArgs, "Weights can't be negative"));
+        }
+        weights.push(weight);
+    }
+    let total: f64 = weights.iter().sum();
+    if !(total > 0.0) {
+        return Err(error!(InvalidArgs, "Expected a weight above zero"));
+    }
+
+    let mut target = seq.rng.next_f64() * total;
+    let mut idx = 0;
+    for (i, weight) in weights.iter().enumerate() {
+        // Values without weight are never chosen
+        if *weight > 0.0 {
+            idx = i;
+        }
+        if target < *weight {
+            break;
+        }
+        target -= weight;
+    }
+    let val = state.heap_get(start + idx)?;
+    state.push(val)?;
+    Ok(None)
+}
+
+/// Push a random number from a normal distribution onto the stack
+#[allow(clippy::neg_cmp_op_on_partial_ord)]
+pub fn gaussian(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let deviation = state.pop_num()?;
+    let mean = state.pop_num()?;
+    // NaN fails the comparison, and would panic when sampled
+    if !(deviation >= 0.0) {
+        return Err(error!(InvalidArgs, "Deviation can't be negative"));
+    }
+    let val = Normal::new(mean, deviation).ind_sample(&mut seq.rng);
+    state.push(Value::Number(val))?;
+    Ok(None)
+}
+
+/// Push a random number from an exponential distribution onto the stack
+#[allow(clippy::neg_cmp_op_on_partial_ord)]
+pub fn exponential(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let rate = state.pop_num()?;
+    // NaN fails the comparison, and would panic when sampled
+    if !(rate > 0.0) {
+        return Err(error!(InvalidArgs, "Rate must be above zero"));
+    }
+    let val = Exp::new(rate).ind_sample(&mut seq.rng);
+    state.push(Value::Number(val))?;
+    Ok(None)
+}
+
+/// Generate a random walk, a sequence of values that each move up or down
+/// from the last by at most a step
+pub fn brownian(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let len = state.pop_num()? as usize;
+    let step = state.pop_num()?;
+    let mut val = state.pop_num()?;
+    if step < 0.0 {
+        return Err(error!(InvalidArgs, "Step can't be negative"));
+    }
+
+    let start = state.heap_len();
+    for _ in 0..len {
+        state.heap_push(Value::Number(val));
+        if step > 0.0 {
+            val += seq.rng.gen_range(-step, step);
+        }
+    }
+
+    let end = state.heap_len();
+    state.push(Value::Seq(start, end))?;
+    Ok(None)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,4 +144,80 @@ mod tests {
         rand_range(&mut seq, &mut state).unwrap();
         assert_eq!(state.pop_num().unwrap(), 31.0);
     }
+
+    #[test]
+    fn test_chance() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        state.call(0, 0, 1).unwrap();
+        for (prob, expected) in [(0.0, Value::Null), (1.0, Value::Number(60.0))] {
+            state.push(Value::Number(60.0)).unwrap();
+            state.push(Value::Number(prob)).unwrap();
+            chance(&mut seq, &mut state).unwrap();
+            assert_eq!(state.pop().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_wchoose() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        state.call(0, 0, 1).unwrap();
+        for val in &[60.0, 62.0, 64.0, 0.0, 3.0, 1.0] {
+            state.heap_push(Value::Number(*val));
+        }
+        let mut counts = [0; 3];
+        for _ in 0..400 {
+            state.push(Value::List(0, 3)).unwrap();
+            state.push(Value::List(3, 6)).unwrap();
+            wchoose(&mut seq, &mut state).unwrap();
+            match state.pop_num().unwrap() as usize {
+                60 => counts[0] += 1,
+                62 => counts[1] += 1,
+                64 => counts[2] += 1,
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(counts[0], 0);
+        assert!(counts[1] > counts[2] * 2);
+
+        state.heap_push(Value::Number(f64::NAN));
+        state.push(Value::List(0, 3)).unwrap();
+        state.push(Value::List(4, 7)).unwrap();
+        assert!(wchoose(&mut seq, &mut state).is_err());
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        state.call(0, 0, 1).unwrap();
+        state.push(Value::Number(60.0)).unwrap();
+        state.push(Value::Number(0.0)).unwrap();
+        gaussian(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 60.0);
+
+        state.push(Value::Number(2.0)).unwrap();
+        exponential(&mut seq, &mut state).unwrap();
+        assert!(state.pop_num().unwrap() >= 0.0);
+
+        state.push(Value::Number(60.0)).unwrap();
+        state.push(Value::Number(f64::NAN)).unwrap();
+        assert!(gaussian(&mut seq, &mut state).is_err());
+        state.push(Value::Number(f64::NAN)).unwrap();
+        assert!(exponential(&mut seq, &mut state).is_err());
+
+        state.push(Value::Number(60.0)).unwrap();
+        state.push(Value::Number(2.0)).unwrap();
+        state.push(Value::Number(8.0)).unwrap();
+        brownian(&mut seq, &mut state).unwrap();
+        let (start, end) = state.pop().unwrap().as_range().unwrap();
+        let walk = state.heap_slice_mut(start, end).unwrap().to_vec();
+        assert_eq!(walk.len(), 8);
+        assert_eq!(walk[0], Value::Number(60.0));
+        for pair in walk.windows(2) {
+            let step = pair[1].as_num().unwrap() - pair[0].as_num().unwrap();
+            assert!(step.abs() <= 2.0);
+        }
+    }
 }
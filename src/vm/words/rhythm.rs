@@ -1,5 +1,7 @@
 use std::iter;
+use std::result;
 
+use crate::err::Error;
 use crate::vm::interp::{InterpState, Value};
 use crate::vm::types::{Result, SeqState};
 
@@ -101,3 +103,197 @@ pub fn onsets(_: &mut SeqState, state: &mut InterpState) -> Result {
     state.push(Value::Seq(heap_start, heap_end))?;
     Ok(None)
 }
+
+/// Collect the sequences in a list, other values becoming a sequence of a
+/// single step
+fn pop_sequences(state: &mut InterpState) -> result::Result<(usize, usize), Error> {
+    let (start, end) = (state.pop()?).as_range()?;
+
+    let mut seqs = Vec::with_capacity(end - start);
+    for ptr in start..end {
+        seqs.push(match state.heap_get(ptr)? {
+            Value::Seq(a, b) => Value::Seq(a, b),
+            value => {
+                let at = state.heap_len();
+                state.heap_push(value);
+                Value::Seq(at, at + 1)
+            }
+        });
+    }
+
+    let heap_start = state.heap_len();
+    for seq in seqs {
+        state.heap_push(seq);
+    }
+    Ok((heap_start, state.heap_len()))
+}
+
+/// Play sequences with different numbers of steps in the same span of time,
+/// each dividing it evenly
+pub fn polyrhythm(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let (start, end) = pop_sequences(state)?;
+    state.push(Value::Group(start, end))?;
+    Ok(None)
+}
+
+/// Play sequences with different numbers of steps at the same rate, with
+/// a number of steps a cycle. Shorter sequences repeat, carrying on from
+/// the step they reached in the cycle before
+pub fn polymeter(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let steps = state.pop_num()? as usize;
+    if steps == 0 {
+        return Err(error!(InvalidArgs, "A polymeter needs at least one step"));
+    }
+    let (start, end) = pop_sequences(state)?;
+    state.push(Value::Polymeter(start, end, steps))?;
+    Ok(None)
+}
+
+/// Spread onsets as evenly as possible over a number of pulses, with
+/// Bjorklund's algorithm
+fn bjorklund(onsets: usize, pulses: usize) -> Vec<bool> {
+    let mut front: Vec<Vec<bool>> = vec![vec![true]; onsets];
+    let mut back: Vec<Vec<bool>> = vec![vec![false]; pulses - onsets];
+
+    while back.len() > 1 && !front.is_empty() {
+        let pairs = front.len().min(back.len());
+        let mut remainder = front.split_off(pairs);
+        remainder.extend(back.drain(pairs..));
+        for (group, rest) in front.iter_mut().zip(back) {
+            group.extend(rest);
+        }
+        back = remainder;
+    }
+
+    front.into_iter().chain(back).flatten().collect()
+}
+
+/// Push a rhythm onto the stack as a sequence of onsets and rests
+fn push_rhythm(state: &mut InterpState, rhythm: Vec<Value>) -> Result {
+    let start = state.heap_len();
+    for value in rhythm {
+        state.heap_push(value);
+    }
+    let end = state.heap_len();
+    state.push(Value::Seq(start, end))?;
+    Ok(None)
+}
+
+/// Generate a Euclidean rhythm, spreading onsets evenly over a number of
+/// pulses then rotating it
+///
+/// Onsets are `1` and rests are `~`. See [1]
+///
+///   [1]: Godfried Toussaint. The Euclidean Algorithm Generates Traditional
+///        Musical Rhythms. Proceedings of BRIDGES, 2005.
+pub fn euclid(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let rotation = state.pop_num()? as usize;
+    let pulses = state.pop_num()? as usize;
+    let onsets = state.pop_num()? as usize;
+
+    if onsets > pulses {
+        return Err(error!(InvalidArgs, "More onsets than pulses"));
+    }
+
+    let mut rhythm: Vec<Value> = bjorklund(onsets, pulses)
+        .into_iter()
+        .map(|onset| {
+            if onset {
+                Value::Number(1.0)
+            } else {
+                Value::Null
+            }
+        })
+        .collect();
+    if !rhythm.is_empty() {
+        let len = rhythm.len();
+        rhythm.rotate_right(rotation % len);
+    }
+    push_rhythm(state, rhythm)
+}
+
+/// Swap the onsets and rests of a rhythm
+pub fn complement(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let (start, end) = (state.pop()?).as_range()?;
+
+    let mut rhythm = Vec::with_capacity(end - start);
+    for ptr in start..end {
+        let onset = (state.heap_get(ptr)?).as_bool();
+        rhythm.push(if onset {
+            Value::Null
+        } else {
+            Value::Number(1.0)
+        });
+    }
+    push_rhythm(state, rhythm)
+}
+
+/// Rotate a rhythm to its canonical form, the rotation that is greatest
+/// when read as a binary number, so that rotations of a rhythm are equal
+pub fn necklace(_: &mut SeqState, state: &mut InterpState) -> Result {
+    let (start, end) = (state.pop()?).as_range()?;
+
+    let mut rhythm = Vec::with_capacity(end - start);
+    for ptr in start..end {
+        rhythm.push(state.heap_get(ptr)?);
+    }
+    let onsets: Vec<bool> = rhythm.iter().map(Value::as_bool).collect();
+    let rotated = |by: usize| onsets[by..].iter().chain(&onsets[..by]);
+    let best = (0..onsets.len())
+        .max_by(|&a, &b| rotated(a).cmp(rotated(b)).then(b.cmp(&a)))
+        .unwrap_or(0);
+
+    rhythm.rotate_left(best);
+    push_rhythm(state, rhythm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(onsets: usize, pulses: usize) -> String {
+        bjorklund(onsets, pulses)
+            .into_iter()
+            .map(|onset| if onset { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn test_bjorklund() {
+        assert_eq!(pattern(3, 8), "x..x..x.");
+        assert_eq!(pattern(5, 8), "x.xx.xx.");
+        assert_eq!(pattern(4, 12), "x..x..x..x..");
+        assert_eq!(pattern(5, 13), "x..x.x..x.x..");
+        assert_eq!(pattern(0, 4), "....");
+        assert_eq!(pattern(4, 4), "xxxx");
+        assert_eq!(pattern(0, 0), "");
+    }
+
+    #[test]
+    fn test_necklace() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        state.call(0, 0, 1).unwrap();
+        state.push(Value::Number(3.0)).unwrap();
+        state.push(Value::Number(8.0)).unwrap();
+        state.push(Value::Number(3.0)).unwrap();
+        euclid(&mut seq, &mut state).unwrap();
+        necklace(&mut seq, &mut state).unwrap();
+        let (start, end) = state.pop().unwrap().as_range().unwrap();
+        let out = state.heap_slice_mut(start, end).unwrap();
+        let (x, rest) = (Value::Number(1.0), Value::Null);
+        assert_eq!(
+            out,
+            &[
+                x.clone(),
+                rest.clone(),
+                x.clone(),
+                rest.clone(),
+                rest.clone(),
+                x,
+                rest.clone(),
+                rest,
+            ]
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::vm::interp::{InterpState, Value};
+use crate::vm::types::{Result, SeqState, BEATS_PER_BAR};
+
+/// Set the tempo in beats per minute
+#[allow(clippy::neg_cmp_op_on_partial_ord)]
+pub fn bpm(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let tempo = state.pop_num()?;
+    // NaN fails the comparison, and would make the duration of every revision NaN
+    if !(tempo > 0.0) {
+        return Err(error!(InvalidArgs));
+    }
+    seq.tempo = tempo;
+    Ok(None)
+}
+
+/// Convert a number of beats to milliseconds at the current tempo
+pub fn beats(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let beats = state.pop_num()?;
+    state.push(Value::Number(seq.beats_to_millis(beats)))?;
+    Ok(None)
+}
+
+/// Convert a number of bars to milliseconds at the current tempo
+pub fn bars(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let bars = state.pop_num()?;
+    let ms = seq.beats_to_millis(bars * BEATS_PER_BAR);
+    state.push(Value::Number(ms))?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_musical_time() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        state.call(0, 0, 1).unwrap();
+        state.push(Value::Number(2.0)).unwrap();
+        beats(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 1000.0);
+        state.push(Value::Number(150.0)).unwrap();
+        bpm(&mut seq, &mut state).unwrap();
+        state.push(Value::Number(1.0)).unwrap();
+        bars(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 1600.0);
+        state.push(Value::Number(0.0)).unwrap();
+        assert!(bpm(&mut seq, &mut state).is_err());
+        state.push(Value::Number(f64::NAN)).unwrap();
+        assert!(bpm(&mut seq, &mut state).is_err());
+        assert_eq!(seq.tempo, 150.0);
+    }
+}
@@ -1,6 +1,15 @@
 use crate::vm::interp::{InterpState, Value};
 use crate::vm::types::{Result, SeqState};
 
+/// Puts the value of a host parameter onto the stack, or zero when the
+/// parameter has not been set
+pub fn param(seq: &mut SeqState, state: &mut InterpState) -> Result {
+    let name = (state.pop()?).as_sym()?;
+    let val = seq.params.get(name).unwrap_or(0.0);
+    state.push(Value::Number(val))?;
+    Ok(None)
+}
+
 /// Puts the current cycle revision onto the stack
 pub fn revision(seq: &mut SeqState, state: &mut InterpState) -> Result {
     state.push(Value::Number(seq.revision as f64))?;
@@ -10,6 +19,7 @@ pub fn revision(seq: &mut SeqState, state: &mut InterpState) -> Result {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lang::hash_str;
 
     #[test]
     fn test_rev() {
@@ -20,4 +30,18 @@ mod tests {
         revision(&mut seq, &mut state).unwrap();
         assert_eq!(state.pop_num().unwrap(), 99.0);
     }
+
+    #[test]
+    fn test_param() {
+        let mut state = InterpState::new();
+        let mut seq = SeqState::new();
+        seq.params.set("density", 0.7);
+        state.call(0, 0, 1).unwrap();
+        state.push(Value::Symbol(hash_str("density"))).unwrap();
+        param(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 0.7);
+        state.push(Value::Symbol(hash_str("unset"))).unwrap();
+        param(&mut seq, &mut state).unwrap();
+        assert_eq!(state.pop_num().unwrap(), 0.0);
+    }
 }
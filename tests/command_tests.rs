@@ -36,12 +36,52 @@ fn test_sieves_xor() {
     command_test!(350.0, "sieves_xor");
 }
 
+#[test]
+fn test_prob_chance() {
+    command_test!(400.0, "prob_chance");
+}
+
 #[test]
 fn test_rhythm_onsets() {
     command_test!(300.0, "rhythm_onsets");
 }
 
+#[test]
+fn test_rhythm_euclid() {
+    command_test!(240.0, "rhythm_euclid");
+}
+
+#[test]
+fn test_rhythm_polymeter() {
+    command_test!(400.0, "rhythm_polymeter");
+}
+
 #[test]
 fn test_rotate_simple() {
     command_test!(200.0, "rotate_simple");
 }
+
+#[test]
+fn test_conditional_simple() {
+    command_test!(400.0, "conditional_simple");
+}
+
+#[test]
+fn test_quote_map() {
+    command_test!(200.0, "quote_map");
+}
+
+#[test]
+fn test_tempo_simple() {
+    command_test!(1800.0, "tempo_simple");
+}
+
+#[test]
+fn test_midi_file() {
+    command_test!(400.0, "midi_file");
+}
+
+#[test]
+fn test_fx_arpeggiate() {
+    command_test!(800.0, "fx_arpeggiate");
+}
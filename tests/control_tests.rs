@@ -0,0 +1,135 @@
+extern crate jez;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::mpsc::channel;
+
+use jez::{Command, Machine, Program, Status};
+
+const PROGRAM: &str = "
+.version 0
+
+.track t1:
+  [60] 500 0 midi_out
+
+.track t2:
+  [64] 500 1 midi_out
+";
+
+/// Run `PROGRAM` for 2600ms, calling `control` at 700ms and `after` at
+/// 1200ms, returning the commands sent and their times
+fn commands<F, G>(clock: bool, control: F, after: G) -> Vec<(f64, Command)>
+where
+    F: Fn(&mut Machine),
+    G: Fn(&mut Machine),
+{
+    let (sender, receiver) = channel();
+    let now = Rc::new(Cell::new(0.0_f64));
+    let time = now.clone();
+
+    let mut machine = Machine::new(
+        &Program::new(PROGRAM).unwrap(),
+        Box::new(|| None),
+        Box::new(move |cmd| sender.send((time.get(), cmd)).unwrap()),
+    )
+    .unwrap();
+    if clock {
+        machine.midi_clock();
+    }
+
+    while now.get() < 2600.0 {
+        if now.get() == 700.0 {
+            control(&mut machine);
+        }
+        if now.get() == 1200.0 {
+            after(&mut machine);
+        }
+        assert_eq!(machine.update(1.0).unwrap(), Status::Continue);
+        now.set(now.get() + 1.0);
+    }
+
+    // Commands reach the sink within a millisecond of their time
+    receiver
+        .try_iter()
+        .map(|(time, cmd)| ((time / 10.0).round() * 10.0, cmd))
+        .collect()
+}
+
+/// The times each channel played a note
+fn run<F, G>(control: F, after: G) -> (Vec<f64>, Vec<f64>)
+where
+    F: Fn(&mut Machine),
+    G: Fn(&mut Machine),
+{
+    let mut notes = (vec![], vec![]);
+    for (time, cmd) in commands(false, control, after) {
+        match cmd {
+            Command::MidiNoteOn(0, _, _) => notes.0.push(time),
+            Command::MidiNoteOn(1, _, _) => notes.1.push(time),
+            _ => (),
+        }
+    }
+    notes
+}
+
+// Tracks are evaluated a revision ahead, so changes at 700ms are heard from
+// the revision starting at 1500ms, and those at 1200ms from 2000ms
+
+#[test]
+fn test_mute_track() {
+    let notes = run(
+        |machine| machine.mute("t2").unwrap(),
+        |machine| machine.unmute("t2").unwrap(),
+    );
+    assert_eq!(notes.0, vec![0.0, 500.0, 1000.0, 1500.0, 2000.0, 2500.0]);
+    assert_eq!(notes.1, vec![0.0, 500.0, 1000.0, 2000.0, 2500.0]);
+}
+
+#[test]
+fn test_solo_track() {
+    let notes = run(
+        |machine| machine.solo("t1").unwrap(),
+        |machine| machine.unsolo("t1").unwrap(),
+    );
+    assert_eq!(notes.0, vec![0.0, 500.0, 1000.0, 1500.0, 2000.0, 2500.0]);
+    assert_eq!(notes.1, vec![0.0, 500.0, 1000.0, 2000.0, 2500.0]);
+}
+
+#[test]
+fn test_stop_and_start_track() {
+    let notes = run(
+        |machine| machine.stop_track("t2").unwrap(),
+        |machine| machine.start_track("t2").unwrap(),
+    );
+    assert_eq!(notes.0, vec![0.0, 500.0, 1000.0, 1500.0, 2000.0, 2500.0]);
+    assert_eq!(notes.1, vec![0.0, 500.0, 1000.0, 2000.0, 2500.0]);
+}
+
+#[test]
+fn test_stop_and_continue_midi_clock() {
+    let cmds = commands(
+        true,
+        |machine| {
+            machine.stop_track("t1").unwrap();
+            machine.stop_track("t2").unwrap();
+        },
+        |machine| machine.start_track("t2").unwrap(),
+    );
+    let transport: Vec<(f64, Command)> = cmds
+        .into_iter()
+        .filter(|(_, cmd)| {
+            matches!(
+                cmd,
+                Command::MidiStart | Command::MidiStop | Command::MidiContinue
+            )
+        })
+        .collect();
+    assert_eq!(
+        transport,
+        vec![
+            (0.0, Command::MidiStart),
+            (1500.0, Command::MidiStop),
+            (1500.0, Command::MidiContinue),
+        ]
+    );
+}
@@ -0,0 +1,76 @@
+extern crate jez;
+extern crate serde_json;
+
+use std::path::{Path, PathBuf};
+
+use jez::{Kind, Program, Resolver};
+
+const PROGRAM: &str = "
+.version 0
+
+.import \"import_lib.jez\"
+
+.track t1:
+  [@root octave] 500 0 midi_out
+";
+
+#[test]
+fn test_import_simple() {
+    // Imports are found next to the file importing them
+    let path = Path::new("tests/files/import_simple.jez");
+    let resolver = Resolver::new(Some(path), &[]);
+    let program = include_str!("files/import_simple.jez");
+    let expected = include_str!("files/import_simple.json");
+
+    let data = jez::simulate_with(500.0, 0.5, program, &resolver).unwrap();
+    let actual: serde_json::Value = serde_json::from_str(&data).unwrap();
+    let expected: serde_json::Value = serde_json::from_str(expected).unwrap();
+    assert_eq!(actual["commands"], expected);
+}
+
+#[test]
+fn test_midi_file_next_to_program() {
+    // Midi files are found next to the program, as imports are
+    let path = Path::new("tests/files/midi_file.jez");
+    let resolver = Resolver::new(Some(path), &[]);
+    let program = include_str!("files/midi_file.jez").replace("tests/files/", "");
+    let expected = include_str!("files/midi_file.json");
+
+    let data = jez::simulate_with(400.0, 0.5, &program, &resolver).unwrap();
+    let actual: serde_json::Value = serde_json::from_str(&data).unwrap();
+    let expected: serde_json::Value = serde_json::from_str(expected).unwrap();
+    assert_eq!(actual["commands"], expected);
+}
+
+#[test]
+fn test_search_paths() {
+    let err = Program::new(PROGRAM).unwrap_err();
+    assert_eq!(err.kind, Kind::Io);
+    assert_eq!(err.span.map(|span| span.line), Some(4));
+
+    let resolver = Resolver::new(None, &[PathBuf::from("tests/files")]);
+    let program = Program::load(PROGRAM, &resolver).unwrap();
+    assert_eq!(program.imports().len(), 1);
+    assert!(program.imports()[0].ends_with("tests/files/import_lib.jez"));
+}
+
+#[test]
+fn test_duplicate_function() {
+    let resolver = Resolver::new(None, &[PathBuf::from("tests/files")]);
+    let program = format!("{}\n.def octave 1:\n  12 subtract\n", PROGRAM);
+    let err = Program::load(&program, &resolver).unwrap_err();
+    assert_eq!(err.kind, Kind::DuplicateFunction);
+    assert_eq!(
+        err.reason.unwrap(),
+        "`octave` is already defined in import_lib.jez"
+    );
+}
+
+#[test]
+fn test_import_cycle() {
+    let path = Path::new("tests/files/import_cycle.jez");
+    let resolver = Resolver::new(Some(path), &[]);
+    let program = include_str!("files/import_cycle.jez");
+    let err = Program::load(program, &resolver).unwrap_err();
+    assert_eq!(err.kind, Kind::CyclicImport);
+}
@@ -0,0 +1,54 @@
+extern crate jez;
+extern crate serde_json;
+
+const TRACKS: &str = "
+.track t1:
+  0 100 rand_range 0 100 rand_range 0 100 rand_range
+  3 beats 1 midi_out
+
+.track t2:
+  (60 62 64 65 67) shuffle 500 2 midi_out
+";
+
+const EXTRA: &str = "
+.track t0:
+  [0 100 rand_range] 250 3 midi_out
+";
+
+/// Pitches played on a channel when simulating a program
+fn pitches(program: &str, chan: u64) -> Vec<f64> {
+    let data = jez::simulate(2000.0, 0.5, program).unwrap();
+    let results: serde_json::Value = serde_json::from_str(&data).unwrap();
+    results["commands"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|cmd| cmd.get("Event"))
+        .filter(|event| event["dest"]["Midi"][0].as_u64() == Some(chan))
+        .map(|event| event["value"]["Trigger"].as_f64().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_tracks_independent() {
+    let program = format!(".version 0\n{}", TRACKS);
+    let extended = format!(".version 0\n{}{}", EXTRA, TRACKS);
+
+    // Adding a track leaves the random values of the others as they were
+    for chan in 1..3 {
+        let before = pitches(&program, chan);
+        assert!(!before.is_empty());
+        assert_eq!(before, pitches(&extended, chan));
+    }
+    assert!(!pitches(&extended, 3).is_empty());
+}
+
+#[test]
+fn test_program_seed() {
+    let program = format!(".version 0\n{}", TRACKS);
+    let seeded = format!(".version 0\n.globals @seed = 7\n{}", TRACKS);
+
+    assert_eq!(pitches(&program, 1), pitches(&program, 1));
+    assert_eq!(pitches(&seeded, 1), pitches(&seeded, 1));
+    assert_ne!(pitches(&program, 1), pitches(&seeded, 1));
+}
@@ -0,0 +1,178 @@
+extern crate jez;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::mpsc::channel;
+
+use jez::{Command, Machine, Program, Status};
+
+const BEFORE: &str = "
+.version 0
+
+.track t1:
+  [60] 500 0 midi_out
+";
+
+const ARPEGGIATED: &str = "
+.version 0
+
+.def main 0:
+  't1 'random 4 1 1 arpeggiate
+  't2 'random 4 1 1 arpeggiate
+
+.track t1:
+  {60 62 64 65 67 69 71} 1000 0 midi_out
+
+.track t2:
+  {48 50 52 53 55 57 59} 1000 1 midi_out
+";
+
+const AFTER: &str = "
+.version 0
+
+.track t1:
+  [64] 500 0 midi_out
+
+.track t2:
+  [72] 1000 1 midi_out
+";
+
+/// Run `before`, reloading `after` at 1200ms, and return the notes played
+fn run_reload(
+    before: &str,
+    after: &str,
+    setup: fn(&mut Machine),
+    duration: f64,
+) -> Vec<(f64, u8, u8)> {
+    let (sender, receiver) = channel();
+    let now = Rc::new(Cell::new(0.0));
+    let time = now.clone();
+
+    let mut machine = Machine::new(
+        &Program::new(before).unwrap(),
+        Box::new(|| None),
+        Box::new(move |cmd| sender.send((time.get(), cmd)).unwrap()),
+    )
+    .unwrap();
+    machine.hot_reload();
+    setup(&mut machine);
+
+    while now.get() < duration {
+        if now.get() == 1200.0 {
+            machine.reload(&Program::new(after).unwrap()).unwrap();
+        }
+        assert_eq!(machine.update(1.0).unwrap(), Status::Continue);
+        now.set(now.get() + 1.0);
+    }
+
+    receiver
+        .try_iter()
+        .filter_map(|(time, cmd)| match cmd {
+            Command::MidiNoteOn(chan, pitch, _) => Some((time, chan, pitch)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn assert_notes(notes: &[(f64, u8, u8)], expected: &[(f64, u8, u8)]) {
+    // Commands reach the sink within a millisecond of their time
+    assert_eq!(notes.len(), expected.len());
+    for (note, expected) in notes.iter().zip(expected) {
+        assert!((note.0 - expected.0).abs() <= 1.0);
+        assert_eq!((note.1, note.2), (expected.1, expected.2));
+    }
+}
+
+#[test]
+fn test_hot_reload_keeps_timing() {
+    // Tracks are evaluated a revision ahead, so the first revision of the
+    // new program to sound for `t1` starts at 2000ms, and new tracks start
+    // alongside it
+    assert_notes(
+        &run_reload(BEFORE, AFTER, |_| (), 3000.0),
+        &[
+            (0.0, 0, 60),
+            (500.0, 0, 60),
+            (1000.0, 0, 60),
+            (1500.0, 0, 60),
+            (2000.0, 0, 64),
+            (2000.0, 1, 72),
+            (2500.0, 0, 64),
+        ],
+    );
+}
+
+#[test]
+fn test_quantized_reload() {
+    // Two bars at 120bpm, the reload lands on the boundary at 4000ms
+    assert_notes(
+        &run_reload(
+            BEFORE,
+            AFTER,
+            |machine| machine.quantize_reload(2.0),
+            5000.0,
+        ),
+        &[
+            (0.0, 0, 60),
+            (500.0, 0, 60),
+            (1000.0, 0, 60),
+            (1500.0, 0, 60),
+            (2000.0, 0, 60),
+            (2500.0, 0, 60),
+            (3000.0, 0, 60),
+            (3500.0, 0, 60),
+            (4000.0, 0, 64),
+            (4000.0, 1, 72),
+            (4500.0, 0, 64),
+        ],
+    );
+}
+
+#[test]
+fn test_quantized_reload_longest() {
+    // `t2` has been evaluated up to 3000ms when the reload arrives, the
+    // next multiple of its length. Tracks pick up the new program from the
+    // revision evaluated alongside it, which for `t1` starts at 2500ms
+    let after = AFTER.replace("[64]", "[67]").replace("[72]", "[74]");
+    assert_notes(
+        &run_reload(
+            AFTER,
+            &after,
+            |machine| machine.quantize_reload_longest(),
+            4000.0,
+        ),
+        &[
+            (0.0, 1, 72),
+            (0.0, 0, 64),
+            (500.0, 0, 64),
+            (1000.0, 1, 72),
+            (1000.0, 0, 64),
+            (1500.0, 0, 64),
+            (2000.0, 0, 64),
+            (2000.0, 1, 72),
+            (2500.0, 0, 67),
+            (3000.0, 0, 67),
+            (3000.0, 1, 74),
+            (3500.0, 0, 67),
+        ],
+    );
+}
+
+#[test]
+fn test_hot_reload_keeps_effects() {
+    let unchanged = run_reload(ARPEGGIATED, ARPEGGIATED, |_| (), 4000.0);
+    let changed = ARPEGGIATED.replace("'t2 'random 4", "'t2 'random 2");
+    let notes = run_reload(ARPEGGIATED, &changed, |_| (), 4000.0);
+
+    // Effects of `t1` keep their state, picking notes as if the program
+    // hadn't been reloaded, while the effects of `t2` are set up again
+    let track = |notes: &[(f64, u8, u8)], chan| {
+        notes
+            .iter()
+            .filter(|note| note.1 == chan)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(track(&notes, 0), track(&unchanged, 0));
+    assert_eq!(track(&notes, 1).len(), 3 * 4 + 2);
+}
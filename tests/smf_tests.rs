@@ -0,0 +1,34 @@
+extern crate jez;
+
+fn chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let len = data[pos + 4..pos + 8]
+            .iter()
+            .fold(0, |len, &byte| (len << 8) | byte as usize);
+        chunks.push(&data[pos..pos + 8 + len]);
+        pos += 8 + len;
+    }
+    chunks
+}
+
+#[test]
+fn test_tracks_to_chunks() {
+    let program = "
+.version 0
+
+.track t1:
+  60 100 0 midi_out
+
+.track t2:
+  64 100 1 midi_out
+";
+
+    let data = jez::simulate_to_smf(100.0, 0.5, program).unwrap();
+    let chunks = chunks(&data);
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(&chunks[0][0..14], b"MThd\0\0\0\x06\0\x01\0\x03\x01\xe0");
+    assert_eq!(&chunks[2][8..], b"\0\x90\x3c\x7f\x60\x80\x3c\0\0\xff\x2f\0");
+    assert_eq!(&chunks[3][8..], b"\0\x91\x40\x7f\x60\x81\x40\0\0\xff\x2f\0");
+}